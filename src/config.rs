@@ -0,0 +1,145 @@
+//! Settings from a TOML file, so the flags a team always uses don't have to be given every time.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, ValueSource};
+use color_eyre::eyre::{eyre, Result};
+use serde::Deserialize;
+use toml::Value;
+
+/** Where we look for the config file when --config isn't given. */
+pub const DEFAULT_CONFIG: &str = "bug-status.toml";
+
+/**
+ * The settings, named like the flags they stand in for, e.g. `bugzilla-url = "..."`.
+ * Top-level settings apply to whichever binaries have that flag,
+ * and the [printing] and [proton] tables to just that binary.
+ */
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    printing: BTreeMap<String, Value>,
+    #[serde(default)]
+    proton: BTreeMap<String, Value>,
+    #[serde(flatten)]
+    shared: BTreeMap<String, Value>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = read_to_string(path).map_err(|err| eyre!("Could not read {}: {}", path.display(), err))?;
+        toml::from_str(&text).map_err(|err| eyre!("Could not parse {}: {}", path.display(), err))
+    }
+
+    /**
+     * The settings as extra command line arguments for the binary, leaving out the flags in `given`.
+     * A binary's own settings override the shared ones, and unknown ones there are an error.
+     */
+    fn args(&self, app: &App, given: &[&str]) -> Result<Vec<OsString>> {
+        let own = match app.get_name() {
+            "printing" => &self.printing,
+            "proton" => &self.proton,
+            name => return Err(eyre!("There are no settings for {}", name)),
+        };
+        let find = |name: &str| app.get_arguments().find(|arg| arg.get_id() == name && arg.get_long().is_some());
+        let mut settings = self.shared
+            .iter()
+            .filter(|(name, value)| !value.is_table() && !own.contains_key(*name) && find(name).is_some())
+            .collect::<Vec<_>>();
+        settings.extend(own);
+
+        let mut rv = vec![];
+        for (name, value) in settings {
+            let arg = find(name).ok_or_else(|| eyre!("{} has no --{} flag to set", app.get_name(), name))?;
+            if given.contains(&name.as_str()) {
+                continue;
+            }
+            let flag = format!("--{}", arg.get_long().unwrap());
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                match value {
+                    Value::Boolean(true) if !arg.is_takes_value_set() => rv.push(flag.clone().into()),
+                    Value::Boolean(false) if !arg.is_takes_value_set() => {}
+                    Value::String(value) => rv.push(format!("{}={}", flag, value).into()),
+                    Value::Integer(_) | Value::Float(_) => rv.push(format!("{}={}", flag, value).into()),
+                    _ => return Err(eyre!("Can't use {} for --{}", value, name)),
+                }
+            }
+        }
+        Ok(rv)
+    }
+}
+
+/** The --config flag, for both binaries. */
+pub fn config_arg() -> Arg<'static> {
+    Arg::new("config")
+        .long("config")
+        .help("Read settings from this TOML file")
+        .long_help(
+            "Read settings from this TOML file, named like the flags, e.g. bugzilla-url = \"...\". \
+            Flags and environment variables win over the file. Defaults to bug-status.toml, if there is one; \
+            see bug-status.example.toml for what can go in it.",
+        )
+        .takes_value(true)
+        .value_name("FILE")
+        .env("BUG_STATUS_CONFIG")
+}
+
+/**
+ * Parse the command line, filling in the flags that weren't given there or in the environment
+ * from the config file.
+ */
+pub fn get_matches(app: &App) -> Result<ArgMatches> {
+    let matches = app.clone().get_matches();
+    let path = match matches.value_of("config") {
+        Some(path) => Path::new(path),
+        None if Path::new(DEFAULT_CONFIG).is_file() => Path::new(DEFAULT_CONFIG),
+        None => return Ok(matches),
+    };
+    let config = Config::load(path)?;
+    let given = app
+        .get_arguments()
+        .map(|arg| arg.get_id())
+        .filter(|id| matches!(matches.value_source(id), Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)))
+        .collect::<Vec<_>>();
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    // Put the settings before any subcommand, which wouldn't know about them.
+    let at = args.len().min(1);
+    args.splice(at..at, config.args(app, &given)?);
+    Ok(app.clone().get_matches_from(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_become_flags_unless_given() {
+        let app = App::new("printing")
+            .arg(Arg::new("bugzilla-url").long("bugzilla-url").takes_value(true))
+            .arg(Arg::new("versions").long("versions").takes_value(true).multiple_occurrences(true))
+            .arg(Arg::new("total").long("total"))
+            .arg(Arg::new("format").long("format").takes_value(true));
+        let config: Config = toml::from_str(r#"
+            bugzilla-url = "https://shared.example"
+            jira-url = "https://jira.example"
+            format = "json"
+
+            [printing]
+            bugzilla-url = "https://printing.example"
+            versions = [81, 82]
+            total = true
+        "#).unwrap();
+        let args = config.args(&app, &["format"]).unwrap();
+        assert_eq!(args, ["--bugzilla-url=https://printing.example", "--total", "--versions=81", "--versions=82"]);
+
+        let config: Config = toml::from_str("[printing]\njira-url = \"https://jira.example\"").unwrap();
+        assert!(config.args(&app, &[]).is_err());
+    }
+}
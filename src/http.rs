@@ -0,0 +1,177 @@
+//! Fetching JSON from Bugzilla, JIRA, and Phabricator, retrying when the network or the server hiccups.
+
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use clap::Arg;
+use color_eyre::eyre::{eyre, Result};
+use indicatif::ProgressBar;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use tracing::{debug_span, warn};
+
+/** How many times to try a request before giving up. */
+const TRIES: u32 = 4;
+/** How long to wait before the first retry, doubling after each one. */
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+/** The longest we'll wait when the server sends a Retry-After. */
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/** Where --dump-raw saves the responses, if it was given. */
+static DUMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+/** How many responses we've saved, to keep their files in order and apart. */
+static DUMPED: AtomicUsize = AtomicUsize::new(0);
+
+/** The --dump-raw flag, for both binaries. */
+pub fn dump_raw_arg() -> Arg<'static> {
+    Arg::new("dump-raw")
+        .long("dump-raw")
+        .help("Save every response from the servers in this directory")
+        .long_help("Save the body of every successful response from Bugzilla, JIRA, and Phabricator in this directory \
+            before parsing it, named by the order it came in and its endpoint, e.g. 0003-rest_bug.json, \
+            to see what the servers sent when a run fails.")
+        .takes_value(true)
+        .value_name("DIR")
+}
+
+/** Save the responses fetch_json gets under `dir` from now on. */
+pub fn dump_raw(dir: &str) -> Result<()> {
+    create_dir_all(dir).map_err(|err| eyre!("Could not create {}: {}", dir, err))?;
+    DUMP_DIR.set(PathBuf::from(dir)).map_err(|_| eyre!("Already saving responses"))
+}
+
+/** Save a response's body for --dump-raw, if it was given. */
+fn dump(url: &Url, body: &str) -> Result<()> {
+    if let Some(dir) = DUMP_DIR.get() {
+        let count = DUMPED.fetch_add(1, Ordering::SeqCst);
+        let endpoint = url.path().trim_matches('/').replace('/', "_");
+        let path = dir.join(format!("{:04}-{}.json", count, endpoint));
+        write(&path, body).map_err(|err| eyre!("Could not save {} to {}: {}", url, path.display(), err))?;
+    }
+    Ok(())
+}
+
+/** Where to show the requests in flight and how long they take, if anywhere. */
+static WATCHER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+/** How many requests are waiting for a response right now. */
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/** Show the requests in flight in this spinner's message from now on, or stop showing them with None. */
+pub fn watch_requests(spinner: Option<ProgressBar>) {
+    *WATCHER.lock().unwrap() = spinner;
+}
+
+fn show_requests(message: String) {
+    if let Some(spinner) = WATCHER.lock().unwrap().as_ref() {
+        spinner.set_message(message);
+    }
+}
+
+/** Counts a request as in flight until it's dropped, when it shows how long the request took. */
+struct InFlight(Instant);
+
+impl InFlight {
+    fn start() -> Self {
+        let count = IN_FLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+        show_requests(format!("{} in flight", count));
+        InFlight(Instant::now())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let count = IN_FLIGHT.fetch_sub(1, Ordering::SeqCst) - 1;
+        show_requests(format!("{} in flight, the last one took {}ms", count, self.0.elapsed().as_millis()));
+    }
+}
+
+/** An error status that retrying won't fix, so callers can tell e.g. bad credentials from other failures. */
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub url: Url,
+}
+
+impl Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Got {} for {}", self.status, self.url)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+impl StatusError {
+    /** Whether the server turned down our credentials. */
+    pub fn is_auth(&self) -> bool {
+        self.status == StatusCode::UNAUTHORIZED || self.status == StatusCode::FORBIDDEN
+    }
+}
+
+/** Server errors and rate limiting usually pass, but other errors like a 404 won't. */
+fn should_retry(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/** How long a rate limited or unavailable server asked us to wait, if it said, in seconds. */
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let seconds = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+}
+
+/**
+ * Send the request and parse the JSON response, retrying with backoff after timeouts,
+ * connection errors, server errors, and rate limiting, or as long as Retry-After says.
+ * Other error statuses fail straight away.
+ */
+pub fn fetch_json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let cant_clone = || eyre!("Could not copy the request to retry it");
+    let url = request.try_clone().ok_or_else(cant_clone)?.build()?.url().clone();
+    let _span = debug_span!("request", %url).entered();
+    let mut delay = FIRST_DELAY;
+    let mut tries = 1;
+    loop {
+        let sent = {
+            let _in_flight = InFlight::start();
+            request.try_clone().ok_or_else(cant_clone)?.send()
+        };
+        let (problem, wait) = match sent {
+            Ok(resp) if resp.status().is_success() => {
+                let body = resp.text().map_err(|err| eyre!("Could not read {}: {}", url, err))?;
+                dump(&url, &body)?;
+                return serde_json::from_str::<T>(&body).map_err(|err| eyre!("Could not parse json from {}: {}", url, err));
+            }
+            Ok(resp) if should_retry(resp.status()) => (format!("Got {}", resp.status()), retry_after(&resp)),
+            Ok(resp) => return Err(StatusError { status: resp.status(), url }.into()),
+            Err(err) if err.is_timeout() || err.is_connect() => (err.to_string(), None),
+            Err(err) => return Err(eyre!("Could not get data for {}: {}", url, err)),
+        };
+        if tries == TRIES {
+            return Err(eyre!("{} for {}, giving up after {} tries", problem, url, tries));
+        }
+        let wait = wait.unwrap_or(delay);
+        warn!("{} for {}, retrying in {}s", problem, url, wait.as_secs());
+        sleep(wait);
+        delay *= 2;
+        tries += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_transient_statuses_are_retried() {
+        assert!(should_retry(StatusCode::BAD_GATEWAY));
+        assert!(should_retry(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!should_retry(StatusCode::NOT_FOUND));
+        assert!(!should_retry(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,78 @@
+//! Code shared between the `printing` and `proton` binaries.
+
+pub mod config;
+pub mod http;
+pub mod mapping;
+pub mod phabricator;
+pub mod servers;
+
+use clap::{App, Arg, ArgMatches};
+use clap_complete::Shell;
+use std::fs::{create_dir_all, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+/**
+ * Load credentials like `JIRA_PASSWORD` from a `.env` file in the current directory, if there is one.
+ * Variables that are already set in the environment win over the file.
+ * Call this before `init_logging`, so a `RUST_LOG` in the file counts; that's also why it can't log.
+ */
+pub fn load_dotenv() {
+    if let Err(err) = dotenvy::dotenv() {
+        if !err.not_found() {
+            eprintln!("Could not read .env: {}", err);
+        }
+    }
+}
+
+/** Where to write the report: the file from `--output`, creating its directory if needed, or stdout. */
+pub fn open_output(path: Option<&str>) -> io::Result<Box<dyn Write>> {
+    match path {
+        Some(path) => {
+            if let Some(dir) = Path::new(path).parent() {
+                create_dir_all(dir)?;
+            }
+            Ok(Box::new(File::create(path)?))
+        }
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+/**
+ * Send log messages to stderr, so they don't get mixed up with the report.
+ * `RUST_LOG` picks what to show, and `RUST_LOG=debug` includes how long each request took.
+ */
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("warn,printing=info,proton=info,bug_status=info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+/** The hidden `completions <SHELL>` subcommand, for packagers and dotfiles rather than everyday use. */
+pub fn completions_command() -> App<'static> {
+    App::new("completions")
+        .about("Print a shell completion script")
+        .hide(true)
+        .arg(
+            Arg::new("shell")
+                .help("The shell to complete for")
+                .required(true)
+                .value_parser(clap::value_parser!(Shell))
+                .value_name("SHELL"),
+        )
+}
+
+/** Print the completion script that the `completions` subcommand asked for. */
+pub fn print_completions(mut app: App, matches: &ArgMatches) {
+    let shell = *matches.get_one::<Shell>("shell").unwrap();
+    let name = app.get_name().to_string();
+    clap_complete::generate(shell, &mut app, name, &mut std::io::stdout());
+}
@@ -0,0 +1,149 @@
+//! How Bugzilla's statuses, priorities, and assignees translate into JIRA's.
+
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Some employees use other addresses in bugzilla.
+    // The JIRA side can also be an account id, for people whose email JIRA hides.
+    static ref EMPLOYEE_EMAILS: HashMap<&'static str, &'static str> = HashMap::from([
+        ("agi@sferro.dev", "asferro@mozilla.com"),
+        ("andrei.br92@gmail.com", "aoprea@mozilla.com"),
+        ("bob.silverberg@gmail.com", "bsilverberg@mozilla.com"),
+        ("dao+bmo@mozilla.com", "dgottwald@mozilla.com"),
+        ("edilee@mozilla.com", "elee@mozilla.com"),
+        ("eitan@monotonous.org", "eisaacson@mozilla.com"),
+        ("emilio@crisal.io", "ealvarez@mozilla.com"),
+        ("enndeakin@gmail.com", "neil@mozilla.com"),
+        ("gijskruitbosch+bugs@gmail.com", "gkruitbosch@mozilla.com"),
+        ("gl@mozilla.com", "gluong@mozilla.com"),
+        ("jaws@mozilla.com", "jwein@mozilla.com"),
+        ("jfkthame@gmail.com", "jkew@mozilla.com"),
+        ("mixedpuppy@gmail.com", "scaraveo@mozilla.com"),
+        ("mozilla@kaply.com", "mkaply@mozilla.com"),
+        ("pbz@mozilla.com", "pzuhlcke@mozilla.com"),
+        ("rob@robwu.nl", "rwu@mozilla.com"),
+        ("tnikkel@gmail.com", "tnikkel@mozilla.com"),
+        ("tomica@gmail.com", "tjovanovic@mozilla.com"),
+    ]);
+}
+
+pub fn jira_status(status: &str, has_patch: bool) -> String {
+    match status {
+        "ASSIGNED" => {
+            if has_patch {
+                "In Review".to_string()
+            } else {
+                "In Progress".to_string()
+            }
+        }
+        "NEW" | "UNCONFIRMED" => "Open".to_string(),
+        "REOPENED" => "Reopened".to_string(),
+        "RESOLVED" => "Closed".to_string(),
+        _ => status.to_string(),
+    }
+}
+
+/**
+ * The extra JIRA statuses that custom workflows use, like "Ready for QA", and the Bugzilla status each one stands for.
+ * We never set these, but an issue that's in one matching its bug's status doesn't need changing.
+ */
+#[derive(Debug, Default)]
+pub struct StatusMap(Vec<(String, String)>);
+
+impl StatusMap {
+    /** Pairs of (JIRA status, Bugzilla status). */
+    pub fn new(statuses: Vec<(String, String)>) -> Self {
+        StatusMap(statuses)
+    }
+
+    /** Whether the JIRA status is one we'd set for the bug, or one that maps back to its Bugzilla status. */
+    pub fn is_consistent(&self, jira: &str, bugzilla: &str, has_patch: bool) -> bool {
+        let jira = jira.trim();
+        jira_status(bugzilla, has_patch).eq_ignore_ascii_case(jira)
+            || self.0.iter().any(|(from, to)| from.trim().eq_ignore_ascii_case(jira) && to.trim().eq_ignore_ascii_case(bugzilla))
+    }
+}
+
+pub fn jira_priority(priority: &str) -> String {
+    match priority {
+        "P1" => "1".to_string(),
+        "P2" => "2".to_string(),
+        "P3" => "3".to_string(),
+        "P4" => "4".to_string(),
+        "P5" => "5".to_string(),
+        "--" => "10000".to_string(),
+        _ => priority.to_string(),
+    }
+}
+
+pub fn jira_assignee(assignee: &str, default_assignee: Option<&str>) -> Option<String> {
+    if let Some(email) = EMPLOYEE_EMAILS.get(assignee) {
+        return Some(email.to_string());
+    }
+    if assignee.ends_with("@mozilla.com") {
+        // Anyone else at Mozilla just gets their address.
+        Some(assignee.to_string())
+    } else {
+        // External contributors go to the default assignee, if there is one.
+        default_assignee.map(|x| x.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assigned_bugs_are_in_progress_or_in_review() {
+        assert_eq!(jira_status("ASSIGNED", true), "In Review");
+        assert_eq!(jira_status("ASSIGNED", false), "In Progress");
+    }
+
+    #[test]
+    fn other_statuses() {
+        assert_eq!(jira_status("NEW", false), "Open");
+        assert_eq!(jira_status("UNCONFIRMED", true), "Open");
+        assert_eq!(jira_status("REOPENED", false), "Reopened");
+        assert_eq!(jira_status("RESOLVED", true), "Closed");
+        assert_eq!(jira_status("VERIFIED", true), "VERIFIED");
+    }
+
+    #[test]
+    fn custom_statuses_map_back() {
+        let map = StatusMap::new(vec![("Ready for QA".to_string(), "RESOLVED".to_string())]);
+        assert!(map.is_consistent("Ready for QA", "RESOLVED", false));
+        assert!(map.is_consistent("Closed", "RESOLVED", false));
+        assert!(!map.is_consistent("Ready for QA", "ASSIGNED", false));
+        assert!(!map.is_consistent("In Review", "ASSIGNED", false));
+        assert!(!StatusMap::default().is_consistent("Ready for QA", "RESOLVED", false));
+    }
+
+    #[test]
+    fn priorities() {
+        assert_eq!(jira_priority("P1"), "1");
+        assert_eq!(jira_priority("P5"), "5");
+        assert_eq!(jira_priority("--"), "10000");
+    }
+
+    #[test]
+    fn employees_with_other_addresses() {
+        assert_eq!(jira_assignee("enndeakin@gmail.com", None), Some("neil@mozilla.com".to_string()));
+        assert_eq!(jira_assignee("dao+bmo@mozilla.com", None), Some("dgottwald@mozilla.com".to_string()));
+    }
+
+    #[test]
+    fn mozilla_addresses_pass_through() {
+        assert_eq!(jira_assignee("someone@mozilla.com", None), Some("someone@mozilla.com".to_string()));
+    }
+
+    #[test]
+    fn external_contributors_get_the_default() {
+        assert_eq!(jira_assignee("contributor@example.com", None), None);
+        assert_eq!(
+            jira_assignee("contributor@example.com", Some("triage@mozilla.com")),
+            Some("triage@mozilla.com".to_string())
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Looking up the Phabricator revisions attached to bugs, so we can spot stalled reviews.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use color_eyre::eyre::{eyre, Result};
+use serde_json::Value;
+
+use crate::http::fetch_json;
+
+pub const DEFAULT_PHABRICATOR: &str = "https://phabricator.services.mozilla.com";
+
+#[derive(Clone, Debug)]
+pub struct Revision {
+    /** The numeric revision id, 12345 for D12345 */
+    pub id: u64,
+    /** The machine-readable status, e.g. needs-review or accepted */
+    pub status: String,
+    /** The status as Phabricator shows it, e.g. Needs Review */
+    pub status_name: String,
+    pub closed: bool,
+    pub modified: DateTime<Utc>,
+}
+
+impl Revision {
+    pub fn needs_review(&self) -> bool {
+        !self.closed && self.status == "needs-review"
+    }
+
+    pub fn age(&self) -> Duration {
+        Utc::now() - self.modified
+    }
+
+    pub fn url(&self, phabricator: &str) -> String {
+        format!("{}/D{}", phabricator.trim_end_matches('/'), self.id)
+    }
+}
+
+/**
+ * Bugzilla names Phabricator request attachments like phabricator-D12345-url.txt,
+ * so pull the revision id out of that.
+ */
+pub fn revision_id(file_name: &str) -> Option<u64> {
+    file_name
+        .strip_prefix("phabricator-D")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/** Look up revisions with Conduit's differential.revision.search, 100 at a time. */
+pub fn get_revisions(phabricator: &str, token: &str, ids: &[u64]) -> Result<HashMap<u64, Revision>> {
+    let url = format!("{}/api/differential.revision.search", phabricator.trim_end_matches('/'));
+    let client = reqwest::blocking::Client::new();
+    let mut revisions = HashMap::new();
+    for chunk in ids.chunks(100) {
+        let mut params = vec![
+            ("api.token".to_string(), token.to_string()),
+            ("limit".to_string(), chunk.len().to_string()),
+        ];
+        for (i, id) in chunk.iter().enumerate() {
+            params.push((format!("constraints[ids][{}]", i), id.to_string()));
+        }
+        let body = fetch_json::<Value>(client.post(&url).form(&params))?;
+        if let Some(error) = body["error_info"].as_str() {
+            return Err(eyre!("Phabricator said {}", error));
+        }
+        let data = body["result"]["data"]
+            .as_array()
+            .ok_or_else(|| eyre!("Could not get revisions from {}", body))?;
+        for item in data {
+            let fields = &item["fields"];
+            let revision = Revision {
+                id: item["id"].as_u64().ok_or_else(|| eyre!("Could not get id from {}", item))?,
+                status: fields["status"]["value"].as_str().unwrap_or_default().to_string(),
+                status_name: fields["status"]["name"].as_str().unwrap_or_default().to_string(),
+                closed: fields["status"]["closed"].as_bool().unwrap_or_default(),
+                modified: Utc.timestamp_opt(fields["dateModified"].as_i64().unwrap_or_default(), 0)
+                    .single()
+                    .ok_or_else(|| eyre!("Could not get dateModified from {}", item))?,
+            };
+            revisions.insert(revision.id, revision);
+        }
+    }
+    Ok(revisions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revision_ids_come_from_attachment_names() {
+        assert_eq!(revision_id("phabricator-D12345-url.txt"), Some(12345));
+        assert_eq!(revision_id("screenshot.png"), None);
+        assert_eq!(revision_id("phabricator-Dxyz-url.txt"), None);
+    }
+}
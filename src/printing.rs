@@ -1,15 +1,39 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::{HashMap, HashSet}, env, fmt::Display, fs::{create_dir_all, File, OpenOptions}, io::{self, BufRead, BufReader, IsTerminal, Write}, path::Path};
 
-use color_eyre::eyre::Result;
+use bug_status::servers::{Servers, DEFAULT_BUGZILLA, DEFAULT_JIRA};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::{App, Arg, ErrorKind};
+use color_eyre::eyre::{eyre, Result};
+use owo_colors::OwoColorize;
 use rayon::prelude::*;
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+
+#[macro_use]
+extern crate clap;
 
 #[derive(Debug)]
+struct OldBug {
+    url: String,
+    priority: String,
+    /** How long the bug has been open, in days */
+    age: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 struct State {
     name: String,
     version: String,
     landed: usize,
+    /** Bugs marked FIXED without a patch of ours, e.g. because the fix landed in another bug. */
+    #[serde(default)]
+    fixed_without_patch: usize,
+    closed_unfixed: usize,
     in_review: usize,
+    /** Patches up for review on the in_review bugs, since a bug can have several. */
+    #[serde(default)]
+    patches_in_review: usize,
     assigned: usize,
     p1_left: usize,
     p2_left: usize,
@@ -19,14 +43,28 @@ struct State {
     p2_open: usize,
     plower_open: usize,
     other_open: usize,
+    #[serde(skip)]
+    old_bugs: Vec<OldBug>,
+    /** Bugs with a status we don't know how to count, as (status, bug id), to report at the end. */
+    #[serde(skip)]
+    unknown_statuses: Vec<(String, i64)>,
+    /** What we made of each bug we counted, for --format bugs. */
+    #[serde(skip)]
+    bugs: Vec<BugRecord>,
+    /** How many open bugs fall into each of the AGE_BUCKETS, for --ages. */
+    #[serde(skip)]
+    ages: [usize; 4],
 }
 impl State {
-    fn new(name: &str, version: i32) -> Self {
+    fn new(name: &str, version: &str) -> Self {
         State {
             name: name.to_owned(),
             version: version.to_string(),
             landed: 0,
+            fixed_without_patch: 0,
+            closed_unfixed: 0,
             in_review: 0,
+            patches_in_review: 0,
             assigned: 0,
             p1_left: 0,
             p2_left: 0,
@@ -36,51 +74,522 @@ impl State {
             p2_open: 0,
             plower_open: 0,
             other_open: 0,
+            old_bugs: vec![],
+            unknown_statuses: vec![],
+            bugs: vec![],
+            ages: [0; 4],
         }
     }
-    fn interesting(&self) -> bool {
-        self.p1_open + self.p2_open + self.plower_open + self.other_open > 0
+    /** Whether to print this state, given how many open bugs it needs; 0 shows everything. */
+    fn interesting(&self, min_open: usize) -> bool {
+        self.total_open() >= min_open
+    }
+    fn total_open(&self) -> usize {
+        self.p1_open + self.p2_open + self.plower_open + self.other_open
+    }
+    fn counters(&self) -> [(&'static str, usize); 14] {
+        [
+            ("landed", self.landed),
+            ("fixed without a patch", self.fixed_without_patch),
+            ("closed without a fix", self.closed_unfixed),
+            ("in review", self.in_review),
+            ("patches in review", self.patches_in_review),
+            ("assigned", self.assigned),
+            ("P1 left", self.p1_left),
+            ("P2 left", self.p2_left),
+            ("P3-P5 left", self.plower_left),
+            ("Unknown left", self.other_left),
+            ("P1 open", self.p1_open),
+            ("P2 open", self.p2_open),
+            ("P3-P5 open", self.plower_open),
+            ("Unknown open", self.other_open),
+        ]
     }
 }
 impl Display for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, false)
+    }
+}
+
+/** Shows a State with the numbers worth noticing in color, for a terminal. */
+struct Colored<'a>(&'a State);
+impl Display for Colored<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.write(f, true)
+    }
+}
+
+impl State {
+    /** Nonzero P1s are red, P2s yellow, and landed patches green, if `color` is set. */
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, color: bool) -> std::fmt::Result {
+        let paint = |count: usize, painted: fn(&usize) -> String| {
+            if color && count > 0 { painted(&count) } else { count.to_string() }
+        };
+        let landed = |count| paint(count, |x| x.green().to_string());
+        let p1 = |count| paint(count, |x| x.red().to_string());
+        let p2 = |count| paint(count, |x| x.yellow().to_string());
         //  Front-end (81 only) - 41 patches landed, 12 patches up for review, 0 more bugs assigned.
         //    Unassigned: 7 P1s, 7 P2s, 4 P3s, 1 Unknown.
         f.write_fmt(format_args!(
-            "  {} (v{}) - {} patches landed, {} patches up for review, {} more bugs assigned.
+            "  {} ({}) - {} patches landed, {} fixed without a patch, {} closed without a fix, {} patches up for review on {} bugs, {} more bugs assigned.
     Without Patches: {} P1s, {} P2s, {} P3-P5s, {} Unknown.
     Total Open:      {} P1s, {} P2s, {} P3-P5s, {} Unknown.",
             self.name,
-            self.version,
-            self.landed,
+            version_label(&self.version),
+            landed(self.landed),
+            self.fixed_without_patch,
+            self.closed_unfixed,
+            self.patches_in_review,
             self.in_review,
             self.assigned,
-            self.p1_left,
-            self.p2_left,
+            p1(self.p1_left),
+            p2(self.p2_left),
             self.plower_left,
             self.other_left,
-            self.p1_open,
-            self.p2_open,
+            p1(self.p1_open),
+            p2(self.p2_open),
             self.plower_open,
             self.other_open,
-        ))
+        ))?;
+        if !self.old_bugs.is_empty() {
+            f.write_str("\n    Old P1s and P2s:")?;
+            for bug in &self.old_bugs {
+                f.write_fmt(format_args!(
+                    "\n      {} ({}, {} days)",
+                    bug.url, bug.priority, bug.age
+                ))?;
+            }
+        }
+        Ok(())
     }
 }
 
-fn categorize_bug(bug: &HashMap<String, Value>, state: &mut State) {
+/** One bug as categorize_bug saw it, for scripts that want more than the counts. */
+#[derive(Debug, Serialize)]
+struct BugRecord {
+    id: i64,
+    /** The bug's title, only shown with --with-summaries since it makes the list much longer. */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    version: String,
+    group: String,
+    product: String,
+    status: String,
+    resolution: Option<String>,
+    priority: String,
+    assignee: Option<String>,
+    open: bool,
+    /** Phabricator requests that aren't obsolete */
+    patches: usize,
+    /**
+     * Which count the bug went into: landed, fixed_without_patch, closed_unfixed, in_review,
+     * or without_patches, or unknown for statuses we don't know.
+     */
+    category: &'static str,
+}
+
+/** Versions are numbers like 81, or 81-85 for totals, but component queries aren't tied to one. */
+fn version_label(version: &str) -> String {
+    if version.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("v{}", version)
+    } else {
+        version.to_string()
+    }
+}
+
+/** The counts from one run, saved so we can see how things change over time. */
+#[derive(Debug, Deserialize)]
+struct Snapshot {
+    time: DateTime<Utc>,
+    states: Vec<State>,
+}
+
+fn read_snapshots(path: &Path) -> Result<Vec<Snapshot>> {
+    if !path.is_file() {
+        return Ok(vec![]);
+    }
+    let mut rv = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            rv.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(rv)
+}
+
+fn to_snapshot(summary: &[Vec<State>]) -> Value {
+    json!({
+        "time": Utc::now(),
+        "states": summary.iter().flatten().collect::<Vec<_>>(),
+    })
+}
+
+fn save_snapshot(path: &Path, summary: &[Vec<State>]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let snapshot = to_snapshot(summary);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", snapshot)?;
+    Ok(())
+}
+
+fn change(old: usize, new: usize) -> String {
+    format!("{} → {} ({:+})", old, new, new as i64 - old as i64)
+}
+
+fn print_trend(out: &mut dyn Write, title: &str, old: &Snapshot, states: &[&State]) -> Result<()> {
+    writeln!(out, "{} ({}):", title, old.time.format("%Y-%m-%d %H:%M"))?;
+    for state in states {
+        let previous = old.states
+            .iter()
+            .find(|x| x.name == state.name && x.version == state.version);
+        let (p1_open, p2_open, total_open) = match previous {
+            Some(previous) => (previous.p1_open, previous.p2_open, previous.total_open()),
+            None => (0, 0, 0),
+        };
+        if (p1_open, p2_open, total_open) == (state.p1_open, state.p2_open, state.total_open()) {
+            continue;
+        }
+        writeln!(
+            out,
+            "  {} ({}): P1s {}, P2s {}, open {}",
+            state.name,
+            version_label(&state.version),
+            change(p1_open, state.p1_open),
+            change(p2_open, state.p2_open),
+            change(total_open, state.total_open()),
+        )?;
+    }
+    Ok(())
+}
+
+/**
+ * Print how many bugs landed per week over the last few weeks of snapshots,
+ * and when the open bugs would be done at that rate.
+ */
+fn print_velocity(out: &mut dyn Write, history: &[Snapshot], states: &[&State], weeks: i64) -> Result<()> {
+    let now = Utc::now();
+    let start = history.iter().find(|x| x.time >= now - Duration::weeks(weeks));
+    let start = match start {
+        Some(start) if now - start.time >= Duration::days(1) => start,
+        _ => {
+            writeln!(out, "Velocity: not enough history yet, it needs snapshots from at least a day ago.")?;
+            return Ok(());
+        }
+    };
+    let elapsed = (now - start.time).num_hours() as f64 / (7 * 24) as f64;
+    writeln!(out, "Velocity over {:.1} weeks (since {}):", elapsed, start.time.format("%Y-%m-%d"))?;
+    for state in states {
+        let previous = start.states
+            .iter()
+            .find(|x| x.name == state.name && x.version == state.version);
+        let previous = match previous {
+            Some(previous) => previous,
+            None => {
+                writeln!(out, "  {} ({}): no history yet", state.name, version_label(&state.version))?;
+                continue;
+            }
+        };
+        let per_week = state.landed.saturating_sub(previous.landed) as f64 / elapsed;
+        let open = state.total_open();
+        let projection = if open == 0 {
+            "nothing left open".to_string()
+        } else if per_week == 0.0 {
+            "nothing landed, so no projection".to_string()
+        } else {
+            let done = now + Duration::hours((open as f64 / per_week * (7 * 24) as f64) as i64);
+            format!("done around {}", done.format("%Y-%m-%d"))
+        };
+        writeln!(
+            out,
+            "  {} ({}): {:.1} landed/week, {} open, {}",
+            state.name,
+            version_label(&state.version),
+            per_week,
+            open,
+            projection,
+        )?;
+    }
+    Ok(())
+}
+
+/**
+ * Estimate how many bugs went from in review to landed since the last snapshot, from how the counters moved.
+ * This only approximates the flow: we don't track bugs between runs, so a bug that landed without
+ * ever being counted in review, or went in and out of review between snapshots, looks the same.
+ */
+fn print_review_throughput(out: &mut dyn Write, last: &Snapshot, states: &[&State]) -> Result<()> {
+    let days = (Utc::now() - last.time).num_minutes() as f64 / (24 * 60) as f64;
+    writeln!(out, "Review throughput since the last snapshot ({}, {:.1} days ago):", last.time.format("%Y-%m-%d %H:%M"), days)?;
+    for state in states {
+        let previous = match last.states.iter().find(|x| x.name == state.name && x.version == state.version) {
+            Some(previous) => previous,
+            None => continue,
+        };
+        let landed = state.landed.saturating_sub(previous.landed);
+        if landed == 0 && state.in_review == previous.in_review {
+            continue;
+        }
+        let rate = if days > 0.0 { format!(" ({:.1}/day)", landed as f64 / days) } else { String::new() };
+        writeln!(
+            out,
+            "  {} ({}): about {} reviewed bugs landed{}, in review {}",
+            state.name,
+            version_label(&state.version),
+            landed,
+            rate,
+            change(previous.in_review, state.in_review),
+        )?;
+    }
+    writeln!(out, "  (Estimated from the counters, so bugs that went in and out of review between snapshots don't show up.)")?;
+    Ok(())
+}
+
+/** Each status categorize_bug didn't know, with the bugs that had it, counting each bug once. */
+fn unknown_statuses(summary: &[Vec<State>]) -> Vec<(String, Vec<i64>)> {
+    let mut rv: Vec<(String, Vec<i64>)> = vec![];
+    for (status, id) in summary.iter().flatten().flat_map(|state| &state.unknown_statuses) {
+        match rv.iter_mut().find(|(known, _)| known == status) {
+            Some((_, ids)) if ids.contains(id) => {}
+            Some((_, ids)) => ids.push(*id),
+            None => rv.push((status.clone(), vec![*id])),
+        }
+    }
+    rv.sort();
+    rv
+}
+
+/** Print how each counter moved between two saved summaries. */
+fn diff(out: &mut dyn Write, old: &str, new: &str) -> Result<()> {
+    let old: Snapshot = serde_json::from_reader(File::open(old)?)?;
+    let new: Snapshot = serde_json::from_reader(File::open(new)?)?;
+    let empty = State::new("", "");
+
+    let mut states = new.states.iter().map(|state| (state.name.as_str(), state.version.as_str())).collect::<Vec<_>>();
+    for state in &old.states {
+        if !states.contains(&(state.name.as_str(), state.version.as_str())) {
+            states.push((state.name.as_str(), state.version.as_str()));
+        }
+    }
+
+    writeln!(out, "From {} to {}:", old.time.format("%Y-%m-%d %H:%M"), new.time.format("%Y-%m-%d %H:%M"))?;
+    for (name, version) in states {
+        let find = |snapshot: &Snapshot| snapshot.states
+            .iter()
+            .position(|x| x.name == name && x.version == version);
+        let old_state = find(&old).map_or(&empty, |i| &old.states[i]);
+        let new_state = find(&new).map_or(&empty, |i| &new.states[i]);
+        let changes = old_state.counters()
+            .iter()
+            .zip(new_state.counters().iter())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((label, old), (_, new))| format!("{}: {}", label, change(*old, *new)))
+            .collect::<Vec<_>>();
+        if !changes.is_empty() {
+            writeln!(out, "  {} ({}) - {}", name, version_label(version), changes.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+type Counter = fn(&State) -> Vec<(Option<&'static str>, usize)>;
+
+/** Print every counter as OpenMetrics text, labelled by version, group, and priority. */
+fn print_prometheus(out: &mut dyn Write, summary: &[Vec<State>], group: &str) -> Result<()> {
+    let families: [(&str, &str, Counter); 8] = [
+        ("bugstatus_landed", "Fixed bugs with patches.", |state| vec![(None, state.landed)]),
+        ("bugstatus_fixed_without_patch", "Fixed bugs without patches.", |state| vec![(None, state.fixed_without_patch)]),
+        ("bugstatus_closed_unfixed", "Bugs closed without a fix.", |state| vec![(None, state.closed_unfixed)]),
+        ("bugstatus_in_review", "Assigned bugs with patches up for review.", |state| vec![(None, state.in_review)]),
+        ("bugstatus_patches_in_review", "Patches up for review on assigned bugs.", |state| vec![(None, state.patches_in_review)]),
+        ("bugstatus_assigned", "Other assigned bugs.", |state| vec![(None, state.assigned)]),
+        ("bugstatus_left", "Open bugs without patches.", |state| vec![
+            (Some("P1"), state.p1_left),
+            (Some("P2"), state.p2_left),
+            (Some("P3-P5"), state.plower_left),
+            (Some("unknown"), state.other_left),
+        ]),
+        ("bugstatus_open", "Open bugs.", |state| vec![
+            (Some("P1"), state.p1_open),
+            (Some("P2"), state.p2_open),
+            (Some("P3-P5"), state.plower_open),
+            (Some("unknown"), state.other_open),
+        ]),
+    ];
+    for (name, help, counter) in families.iter() {
+        writeln!(out, "# TYPE {} gauge", name)?;
+        writeln!(out, "# HELP {} {}", name, help)?;
+        for state in summary.iter().flatten() {
+            let labels = format!(
+                "version=\"{}\",{}=\"{}\"",
+                state.version,
+                group,
+                state.name.to_lowercase().replace('\\', "\\\\").replace('"', "\\\""),
+            );
+            for (priority, value) in counter(state) {
+                match priority {
+                    Some(priority) => writeln!(out, "{}{{{},priority=\"{}\"}} {}", name, labels, priority, value)?,
+                    None => writeln!(out, "{}{{{}}} {}", name, labels, value)?,
+                }
+            }
+        }
+    }
+    writeln!(out, "# EOF")?;
+    Ok(())
+}
+
+/** Print a row of counters for each interesting version and group, in columns sized to fit. */
+fn print_table(out: &mut dyn Write, summary: &[Vec<State>], group: &str, min_open: usize) -> Result<()> {
+    let mut rows = vec![
+        ["version", group].iter().map(|x| x.to_string())
+            .chain(State::new("", "").counters().iter().map(|(label, _)| label.to_string()))
+            .collect::<Vec<_>>(),
+    ];
+    for state in summary.iter().flatten().filter(|state| state.interesting(min_open)) {
+        rows.push(
+            vec![version_label(&state.version), state.name.clone()].into_iter()
+                .chain(state.counters().iter().map(|(_, count)| count.to_string()))
+                .collect(),
+        );
+    }
+    let widths = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap())
+        .collect::<Vec<_>>();
+    for (i, row) in rows.iter().enumerate() {
+        // The version and group read better on the left, and the numbers on the right.
+        let cells = row.iter().zip(&widths).enumerate().map(|(column, (cell, width))| {
+            if column < 2 { format!("{:<width$}", cell, width = width) } else { format!("{:>width$}", cell, width = width) }
+        });
+        writeln!(out, "{}", cells.collect::<Vec<_>>().join("  ").trim_end())?;
+        if i == 0 {
+            writeln!(out, "{}", widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>().join("  "))?;
+        }
+    }
+    Ok(())
+}
+
+/** The --ages buckets, as a label and the oldest a bug in the bucket can be, in days. */
+const AGE_BUCKETS: [(&str, i64); 4] = [("0-7 days", 7), ("8-30 days", 30), ("31-90 days", 90), ("90+ days", i64::MAX)];
+
+/** Print a bar for each of the AGE_BUCKETS, showing how long each group's open bugs have been open. */
+fn print_ages(out: &mut dyn Write, states: &[&State]) -> Result<()> {
+    // Keep the bars short enough for a terminal, however many bugs there are.
+    let most = states.iter().flat_map(|state| state.ages).max().unwrap_or_default().max(40);
+    writeln!(out, "Open bugs by age:")?;
+    for state in states {
+        writeln!(out, "  {} ({})", state.name, version_label(&state.version))?;
+        for ((label, _), count) in AGE_BUCKETS.iter().zip(state.ages) {
+            let bar = "#".repeat((count * 40).div_ceil(most));
+            writeln!(out, "    {:<10}  {:<40}  {}", label, bar, count)?;
+        }
+    }
+    Ok(())
+}
+
+/** How many days ago the bug was filed, if Bugzilla told us. */
+fn bug_age(bug: &HashMap<String, Value>) -> Option<i64> {
+    bug.get("creation_time")
+        .and_then(|x| x.as_str())
+        .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+        .map(|created| (Utc::now() - created.with_timezone(&Utc)).num_days())
+}
+
+/** List the open bugs that are older than their severity's target, grouped by severity and product. */
+fn print_sla(out: &mut dyn Write, bugs: &[&Bug], targets: &[(&str, i64)], servers: &Servers) -> Result<()> {
+    writeln!(out, "SLA breaches:")?;
+    let mut breaches = 0;
+    for (severity, days) in targets {
+        let mut late = bugs
+            .iter()
+            .filter(|bug| ["NEW", "ASSIGNED", "UNCONFIRMED", "REOPENED"].contains(&bug["status"].as_str().unwrap()))
+            .filter(|bug| bug.get("severity").and_then(|x| x.as_str()) == Some(severity))
+            .filter_map(|bug| bug_age(bug).filter(|age| age > days).map(|age| (bug, age)))
+            .collect::<Vec<_>>();
+        late.sort_by_key(|(bug, age)| (bug["product"].as_str().unwrap().to_string(), -age));
+        let mut products = late.iter().map(|(bug, _)| bug["product"].as_str().unwrap()).collect::<Vec<_>>();
+        products.dedup();
+        for product in products {
+            let in_product = late.iter().filter(|(bug, _)| bug["product"].as_str() == Some(product)).collect::<Vec<_>>();
+            writeln!(out, "  {} {} (over {} days): {} bugs", severity, product, days, in_product.len())?;
+            for (bug, age) in in_product {
+                writeln!(out, "    {} ({} days)", servers.bug_url(&bug["id"].to_string()), age)?;
+            }
+        }
+        breaches += late.len();
+    }
+    if breaches == 0 {
+        writeln!(out, "  None!")?;
+    }
+    Ok(())
+}
+
+fn check_age(bug: &HashMap<String, Value>, state: &mut State, max_age: i64, servers: &Servers) {
     let status = bug["status"].as_str().unwrap();
     let priority = bug["priority"].as_str().unwrap();
-    let mut has_patch = false;
-    let attachments = bug["attachments"].as_array().unwrap();
-    for attachment in attachments {
-        if attachment["content_type"].as_str().unwrap() == "text/x-phabricator-request" {
-            has_patch = true;
+    if !["NEW", "ASSIGNED"].contains(&status) || !["P1", "P2"].contains(&priority) {
+        return;
+    }
+    if let Some(age) = bug_age(bug) {
+        if age > max_age {
+            state.old_bugs.push(OldBug {
+                url: servers.bug_url(&bug["id"].to_string()),
+                priority: priority.to_owned(),
+                age,
+            });
+        }
+    }
+}
+
+/** Accept an RFC 3339 timestamp, or a date and optional time in UTC, like 2020-10-01 or 2020-10-01T12:00:00. */
+fn parse_date(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    for format in &["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(Utc.from_utc_datetime(&date));
         }
     }
-    match status {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| eyre!("Could not understand the date {:?}, try 2020-10-01", value))?;
+    Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+}
+
+/** Whether the bug changed after `since`. Older saved responses don't say, so count those. */
+fn changed_since(bug: &HashMap<String, Value>, since: &DateTime<Utc>) -> bool {
+    bug.get("last_change_time")
+        .and_then(|x| x.as_str())
+        .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+        .is_none_or(|changed| changed.with_timezone(&Utc) > *since)
+}
+
+fn categorize_bug(bug: &HashMap<String, Value>, state: &mut State) {
+    let status = bug["status"].as_str().unwrap();
+    let priority = bug["priority"].as_str().unwrap();
+    // Older saved responses don't have resolutions, so assume they were fixed.
+    let resolution = bug.get("resolution").and_then(|x| x.as_str()).unwrap_or("FIXED");
+    // Bugs without attachments, or whose attachments we can't see, may not have the field at all.
+    let empty = vec![];
+    let attachments = bug.get("attachments").and_then(|x| x.as_array()).unwrap_or(&empty);
+    let patch_count = attachments.iter().filter(|attachment| {
+        attachment.get("content_type").and_then(|x| x.as_str()) == Some("text/x-phabricator-request")
+            && attachment.get("is_obsolete").and_then(|x| x.as_u64()).unwrap_or_default() != 1
+    }).count();
+    let has_patch = patch_count > 0;
+    let category = match status {
         "RESOLVED" | "VERIFIED" => {
-            if has_patch {
+            if resolution != "FIXED" {
+                state.closed_unfixed += 1;
+                "closed_unfixed"
+            } else if has_patch {
                 state.landed += 1;
+                "landed"
+            } else {
+                state.fixed_without_patch += 1;
+                "fixed_without_patch"
             }
         }
         "ASSIGNED" => {
@@ -92,6 +601,8 @@ fn categorize_bug(bug: &HashMap<String, Value>, state: &mut State) {
             }
             if has_patch {
                 state.in_review += 1;
+                state.patches_in_review += patch_count;
+                "in_review"
             } else {
                 match priority {
                     "P1" => state.p1_left += 1,
@@ -99,76 +610,1027 @@ fn categorize_bug(bug: &HashMap<String, Value>, state: &mut State) {
                     "P3" | "P4" | "P5" => state.plower_left += 1,
                     _ => state.other_left += 1,
                 }
+                "without_patches"
             }
         }
-        "NEW" | "UNCONFIRMED" | "REOPENED" => match priority {
-            "P1" => {
-                state.p1_left += 1;
-                state.p1_open += 1
-            }
-            "P2" => {
-                state.p2_left += 1;
-                state.p2_open += 1
-            }
-            "P3" | "P4" | "P5" => {
-                state.plower_left += 1;
-                state.plower_open += 1
-            }
-            _ => {
-                state.other_left += 1;
-                state.other_open += 1
+        "NEW" | "UNCONFIRMED" | "REOPENED" => {
+            match priority {
+                "P1" => {
+                    state.p1_left += 1;
+                    state.p1_open += 1
+                }
+                "P2" => {
+                    state.p2_left += 1;
+                    state.p2_open += 1
+                }
+                "P3" | "P4" | "P5" => {
+                    state.plower_left += 1;
+                    state.plower_open += 1
+                }
+                _ => {
+                    state.other_left += 1;
+                    state.other_open += 1
+                }
             }
-        },
+            "without_patches"
+        }
         _ => {
-            println!("Unknown status!!!\n  {}\n  Bug {:?}\n", status, bug);
+            state.unknown_statuses.push((status.to_string(), bug["id"].as_i64().unwrap_or_default()));
+            "unknown"
         }
+    };
+    let open = ["NEW", "ASSIGNED", "UNCONFIRMED", "REOPENED"].contains(&status);
+    if let Some(age) = bug_age(bug).filter(|_| open) {
+        let bucket = AGE_BUCKETS.iter().position(|(_, days)| age <= *days).unwrap();
+        state.ages[bucket] += 1;
+    }
+    state.bugs.push(BugRecord {
+        id: bug["id"].as_i64().unwrap_or_default(),
+        summary: bug.get("summary").and_then(|x| x.as_str()).map(|x| x.to_string()),
+        version: state.version.clone(),
+        group: state.name.clone(),
+        product: bug.get("product").and_then(|x| x.as_str()).unwrap_or_default().to_string(),
+        status: status.to_string(),
+        resolution: bug.get("resolution").and_then(|x| x.as_str()).filter(|x| !x.is_empty()).map(|x| x.to_string()),
+        priority: priority.to_string(),
+        assignee: bug.get("assigned_to").and_then(|x| x.as_str()).filter(|x| *x != "nobody@mozilla.org").map(|x| x.to_string()),
+        open,
+        patches: patch_count,
+        category,
+    });
+}
+
+fn get_state<'a>(states: &'a mut Vec<State>, name: &str, version: &str) -> &'a mut State {
+    if let Some(index) = states.iter().position(|state| state.name == name) {
+        &mut states[index]
+    } else {
+        states.push(State::new(name, version));
+        states.last_mut().unwrap()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionPart {
+    Number(u64),
+    Text(String),
+}
+
+/** Split a version into runs of digits and other text, so 9 sorts before 10 and v99 before v100. */
+fn version_key(version: &str) -> Vec<VersionPart> {
+    let mut parts = vec![];
+    let mut rest = version;
+    while let Some(first) = rest.chars().next() {
+        let digits = first.is_ascii_digit();
+        let end = rest.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(rest.len());
+        let (part, tail) = rest.split_at(end);
+        parts.push(match part.parse() {
+            Ok(number) if digits => VersionPart::Number(number),
+            _ => VersionPart::Text(part.to_string()),
+        });
+        rest = tail;
     }
+    parts
+}
+
+/** A bug as Bugzilla's REST API returns it. */
+type Bug = HashMap<String, Value>;
+
+/**
+ * Bugzilla's query parameters for the bugs in any of the components, as (product, component) pairs.
+ * Repeating product= and component= would also match other products' components with the same name,
+ * so this uses a custom search, OR-ing a product AND component clause for each one.
+ */
+fn component_query(components: &[(&str, &str)]) -> Vec<(String, String)> {
+    let mut params = vec![];
+    if components.is_empty() {
+        return params;
+    }
+    // One OR group of clauses, so it still ANDs with the whiteboard tag.
+    params.push(("f1".to_string(), "OP".to_string()));
+    params.push(("j1".to_string(), "OR".to_string()));
+    let mut field = 1;
+    let mut add = |params: &mut Vec<(String, String)>, name: &str, operator: Option<&str>, value: Option<&str>| {
+        field += 1;
+        params.push((format!("f{}", field), name.to_string()));
+        if let Some(operator) = operator {
+            params.push((format!("o{}", field), operator.to_string()));
+        }
+        if let Some(value) = value {
+            params.push((format!("v{}", field), value.to_string()));
+        }
+    };
+    for (product, component) in components {
+        add(&mut params, "OP", None, None);
+        add(&mut params, "product", Some("equals"), Some(product));
+        add(&mut params, "component", Some("equals"), Some(component));
+        add(&mut params, "CP", None, None);
+    }
+    add(&mut params, "CP", None, None);
+    params
+}
+
+/**
+ * Get the bugs for a version: the ones with any of the tags' whiteboard tag for it, like [print2020_v81],
+ * and matching the filters, like component_query's, if there are any.
+ * No tags means the filters' bugs for every version.
+ */
+fn get_bugs(version: &str, tags: &[&str], filters: &[(String, String)], input: Option<&str>, servers: &Servers, api_key: Option<&str>) -> Vec<HashMap<String, Value>> {
+    let mut resp = if let Some(input) = input {
+        let path = input.replace("{version}", version);
+        let file = File::open(&path).unwrap_or_else(|_| panic!("Could not open {} for {}", path, version));
+        serde_json::from_reader::<_, HashMap<String, Vec<Bug>>>(file)
+            .unwrap_or_else(|_| panic!("Could not parse json from {}", path))
+    } else {
+        let mut params = vec![("include_fields".to_string(), "id,summary,status,resolution,product,priority,creation_time,assigned_to,assigned_to_detail,last_change_time,severity,keywords,depends_on,blocks,attachments.content_type,attachments.is_obsolete".to_string())];
+        if !tags.is_empty() {
+            // Bugzilla ORs the space-separated tags, and only returns a bug once if it has several of them.
+            let tags = tags.iter().map(|tag| format!("[{}_v{}]", tag, version)).collect::<Vec<_>>();
+            params.push(("status_whiteboard".to_string(), tags.join(" ")));
+            params.push(("status_whiteboard_type".to_string(), "anywordssubstr".to_string()));
+        }
+        params.extend_from_slice(filters);
+        return query_bugs(&params, version, servers, api_key);
+    };
+    resp.remove("bugs").unwrap_or_else(|| panic!("Could not get bugs for {}", version))
+}
+
+/** Run a Bugzilla bug search, describing it as `what` if it fails. */
+fn query_bugs(params: &[(String, String)], what: &str, servers: &Servers, api_key: Option<&str>) -> Vec<Bug> {
+    let url = reqwest::Url::parse_with_params(&format!("{}/rest/bug", servers.bugzilla), params)
+        .unwrap_or_else(|_| panic!("Could not build the query for {}", what));
+    let mut request = reqwest::blocking::Client::new().get(url);
+    if let Some(api_key) = api_key {
+        request = request.header("X-BUGZILLA-API-KEY", api_key);
+    }
+    let mut resp = bug_status::http::fetch_json::<HashMap<String, Vec<Bug>>>(request)
+        .unwrap_or_else(|err| panic!("Could not get bugs for {}: {}", what, err));
+    resp.remove("bugs").unwrap_or_else(|| panic!("Could not get bugs for {}", what))
+}
+
+/** Every version's bugs, with bugs tagged for several versions only included once. */
+fn unique_bugs(counted: &[Vec<Bug>]) -> Vec<&Bug> {
+    let mut seen = HashSet::new();
+    counted
+        .iter()
+        .flatten()
+        .filter(|bug| seen.insert(bug["id"].as_i64().unwrap()))
+        .collect()
+}
+
+/** Print the bugs and the dependencies between them as a Graphviz graph, coloured by priority. */
+fn print_dot(out: &mut dyn Write, bugs: &[&Bug]) -> Result<()> {
+    let ids = bugs.iter().map(|bug| bug["id"].as_u64().unwrap()).collect::<HashSet<_>>();
+    writeln!(out, "digraph bugs {{")?;
+    writeln!(out, "  rankdir=LR;")?;
+    writeln!(out, "  node [shape=box, style=filled];")?;
+    let mut edges = vec![];
+    for bug in bugs {
+        let id = bug["id"].as_u64().unwrap();
+        let summary = bug.get("summary").and_then(|x| x.as_str()).unwrap_or_default();
+        let color = match bug["priority"].as_str().unwrap() {
+            "P1" => "tomato",
+            "P2" => "orange",
+            "P3" | "P4" | "P5" => "lightblue",
+            _ => "white",
+        };
+        let closed = ["RESOLVED", "VERIFIED", "CLOSED"].contains(&bug["status"].as_str().unwrap());
+        writeln!(out, "  {} [label=\"{}: {}\", fillcolor={}{}];",
+            id, id, summary.replace('\\', "\\\\").replace('"', "\\\""), color,
+            if closed { ", style=\"filled,dashed\"" } else { "" })?;
+        // An edge points from a bug to the bug it's waiting on.
+        edges.extend(blockers(bug).into_iter().map(|blocker| (id, blocker)));
+        let blocks = bug.get("blocks").and_then(|x| x.as_array());
+        edges.extend(blocks.into_iter().flatten().filter_map(|x| x.as_u64()).map(|blocked| (blocked, id)));
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    for (from, to) in edges {
+        if ids.contains(&from) && ids.contains(&to) {
+            writeln!(out, "  {} -> {};", from, to)?;
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/** The ids of the bugs this one depends on. */
+fn blockers(bug: &Bug) -> Vec<u64> {
+    bug.get("depends_on")
+        .and_then(|x| x.as_array())
+        .map(|ids| ids.iter().filter_map(|id| id.as_u64()).collect())
+        .unwrap_or_default()
+}
+
+/** Who the bug is assigned to, by name when Bugzilla has one for them, or None if nobody is. */
+fn assignee(bug: &Bug) -> Option<&str> {
+    match bug.get("assigned_to").and_then(|x| x.as_str()) {
+        None | Some("nobody@mozilla.org") => None,
+        Some(assignee) => Some(bug.get("assigned_to_detail")
+            .and_then(|x| x["real_name"].as_str())
+            .filter(|x| !x.is_empty())
+            .unwrap_or(assignee)),
+    }
+}
+
+/** List the bugs someone has taken, but never moved to ASSIGNED. */
+fn print_assigned_new(out: &mut dyn Write, bugs: &[&Bug], servers: &Servers) -> Result<()> {
+    writeln!(out, "Assigned bugs that are still NEW:")?;
+    let mut count = 0;
+    for bug in bugs {
+        let status = bug["status"].as_str().unwrap();
+        if !["NEW", "UNCONFIRMED"].contains(&status) {
+            continue;
+        }
+        if let Some(assignee) = assignee(bug) {
+            writeln!(out, "  {} ({}, assigned to {})", servers.bug_url(&bug["id"].to_string()), status, assignee)?;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        writeln!(out, "  None!")?;
+    }
+    Ok(())
+}
+
+/** The bug's Bugzilla keywords, like regression or sec-high. */
+fn keywords(bug: &Bug) -> Vec<&str> {
+    bug.get("keywords")
+        .and_then(|x| x.as_array())
+        .map(|keywords| keywords.iter().filter_map(|x| x.as_str()).collect())
+        .unwrap_or_default()
+}
+
+/** Count the open bugs with each of the --keyword keywords, with a link to list them. */
+fn print_keywords(out: &mut dyn Write, bugs: &[&Bug], wanted: &[&str], servers: &Servers) -> Result<()> {
+    writeln!(out, "Open bugs by keyword:")?;
+    for keyword in wanted {
+        let ids = bugs
+            .iter()
+            .filter(|bug| ["NEW", "ASSIGNED", "UNCONFIRMED", "REOPENED"].contains(&bug["status"].as_str().unwrap()))
+            .filter(|bug| keywords(bug).iter().any(|x| x.eq_ignore_ascii_case(keyword)))
+            .map(|bug| bug["id"].to_string())
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            writeln!(out, "  {}: 0 bugs", keyword)?;
+        } else {
+            writeln!(out, "  {}: {} bugs, {}/buglist.cgi?bug_id={}", keyword, ids.len(), servers.bugzilla, ids.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * List the open P1s that depend on other bugs. With `statuses`, only list the blockers that are still open,
+ * and skip bugs whose blockers are all closed.
+ */
+fn print_blocked(out: &mut dyn Write, bugs: &[&Bug], statuses: Option<&HashMap<u64, String>>, servers: &Servers) -> Result<()> {
+    writeln!(out, "Blocked P1s:")?;
+    let mut blocked = 0;
+    for bug in bugs {
+        let status = bug["status"].as_str().unwrap();
+        if !["NEW", "ASSIGNED", "UNCONFIRMED", "REOPENED"].contains(&status) || bug["priority"].as_str() != Some("P1") {
+            continue;
+        }
+        let mut open = vec![];
+        for id in blockers(bug) {
+            match statuses {
+                Some(statuses) => match statuses.get(&id).map(|x| x.as_str()) {
+                    Some("RESOLVED") | Some("VERIFIED") | Some("CLOSED") => {}
+                    Some(status) => open.push(format!("{} ({})", id, status)),
+                    None => open.push(format!("{} (private)", id)),
+                },
+                None => open.push(id.to_string()),
+            }
+        }
+        if !open.is_empty() {
+            writeln!(out, "  {} blocked by {}", servers.bug_url(&bug["id"].to_string()), open.join(", "))?;
+            blocked += 1;
+        }
+    }
+    if blocked == 0 {
+        writeln!(out, "  None!")?;
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
-    let summary: Vec<(State,State)> = (81..=85).into_par_iter().map(|version: i32| {
-        let mut frontend_state: State = State::new("Front-end", version);
-        let mut platform_state: State = State::new("Platform", version);
-        let mut seen = vec![];
+    bug_status::load_dotenv();
+    bug_status::init_logging();
+
+    let app = app_from_crate!("\n")
+    .name("printing")
+    .about("Summarize the status of the bugs for each Firefox version")
+    .long_about("Summarize the status of the bugs tagged [print2020_vNN] (or another --whiteboard tag) in Bugzilla for each Firefox version: \
+        how many have landed, are up for review, or are still open, by priority.")
+    .after_long_help("EXAMPLES:
+    Summarize the current bugs:
+        printing
 
-        let url = format!("https://bugzilla.mozilla.org/rest/bug?whiteboard=[print2020_v{}]&include_fields=id,summary,status,product,priority,attachments.content_type", version);
-        // println!("Getting data for {}", url);
-        let resp = reqwest::blocking::get(&url).unwrap_or_else(|_| panic!("Could not get data for {}", version))
-            .json::<HashMap<String, Vec<HashMap<String, Value>>>>().unwrap_or_else(|_| panic!("Could not parse json for {}", version));
-        let bugs = &resp["bugs"];
+    Group the bugs by assignee, and list P1s and P2s older than a month:
+        printing --by assignee --max-age 30
 
-        // let raw = include_str!("../bug-data.json");
-        // let data = serde_json::from_str::<HashMap<String, Vec<HashMap<String, Value>>>>(raw)?;
-        // let bugs = &data["bugs"];
+    Save a snapshot, and show what changed in the last week:
+        printing --snapshot-dir history --trend
+
+    Show how fast bugs are landing, and when each version would be done:
+        printing --snapshot-dir history --velocity
+
+    Compare two saved summaries:
+        printing --format json > new.json
+        printing diff old.json new.json")
+    .arg(
+        Arg::new("input")
+            .short('i')
+            .long("input")
+            .help("Read bugs from a saved Bugzilla response")
+            .long_help("Read bugs from a saved Bugzilla response instead of querying the network. \
+                Any {version} in the path is replaced with the version being summarized, \
+                e.g. bugs-v{version}.json.")
+            .takes_value(true)
+            .value_name("FILE"),
+    )
+    .arg(
+        Arg::new("versions")
+            .long("versions")
+            .help("Which versions to summarize")
+            .long_help("Which versions to summarize, as a comma-separated list. Each one is looked up with the \
+                [<TAG>_v<VERSION>] whiteboard tag for each --whiteboard tag, and they're shown in release order however they're given.")
+            .takes_value(true)
+            .value_name("VERSION")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .default_value("81,82,83,84,85"),
+    )
+    .arg(
+        Arg::new("whiteboard")
+            .long("whiteboard")
+            .help("The whiteboard tag to look for, without the version")
+            .long_help("The whiteboard tag to look for, without the brackets or version, so print2020 finds [print2020_v81]. \
+                Can be given more than once, e.g. --whiteboard print2020 --whiteboard print2021, \
+                to count the bugs with any of the tags, each bug only once.")
+            .takes_value(true)
+            .value_name("TAG")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .default_value("print2020"),
+    )
+    .arg(
+        Arg::new("component")
+            .long("component")
+            .help("Summarize the bugs in a component, as Product::Component")
+            .long_help("Summarize the bugs in this component instead of the ones with the version whiteboard tags, \
+                e.g. --component \"Toolkit::Printing\". Can be given more than once to include bugs from any of the components. \
+                If --versions is also given, only count the bugs that are in one of the components and tagged for the version.")
+            .takes_value(true)
+            .value_name("PRODUCT::COMPONENT")
+            .multiple_occurrences(true),
+    )
+    .arg(
+        Arg::new("saved-search")
+            .long("saved-search")
+            .help("Summarize the bugs from one of your saved Bugzilla searches")
+            .long_help("Summarize the bugs from the Bugzilla saved search with this name, instead of the ones with the \
+                version whiteboard tags. Saved searches belong to an account, so this needs --api-key, \
+                and searches other people shared with you also need --sharer-id. \
+                If --versions is also given, only count the bugs that the search finds and are tagged for the version.")
+            .takes_value(true)
+            .value_name("NAME")
+            .conflicts_with("component"),
+    )
+    .arg(
+        Arg::new("sharer-id")
+            .long("sharer-id")
+            .help("The Bugzilla user id of whoever shared the --saved-search")
+            .takes_value(true)
+            .value_name("ID")
+            .requires("saved-search"),
+    )
+    .arg(
+        Arg::new("bugzilla-url")
+            .long("bugzilla-url")
+            .help("The Bugzilla instance to use")
+            .takes_value(true)
+            .value_name("URL")
+            .env("BUGZILLA_URL")
+            .default_value(DEFAULT_BUGZILLA),
+    )
+    .arg(
+        Arg::new("api-key")
+            .long("api-key")
+            .help("A Bugzilla API key, to see bugs that need a login")
+            .long_help("A Bugzilla API key, sent with the query so restricted bugs are counted too. \
+                Without one the query is anonymous.")
+            .takes_value(true)
+            .value_name("KEY")
+            .env("BUGZILLA_API_KEY")
+            .hide_env_values(true),
+    )
+    .arg(
+        Arg::new("max-age")
+            .long("max-age")
+            .help("List P1 and P2 bugs open for longer than this many days")
+            .long_help("List the NEW and ASSIGNED P1 and P2 bugs that were filed more than this many days ago, oldest first.")
+            .takes_value(true)
+            .value_name("DAYS"),
+    )
+    .arg(
+        Arg::new("format")
+            .short('f')
+            .long("format")
+            .help("How to print the summary")
+            .long_help("Print the summary as text for people, as JSON for the diff command and other scripts, \
+                as YAML with the same layout as the JSON, \
+                as OpenMetrics text for Prometheus, as a Graphviz graph of the bugs and their dependencies, \
+                as one line of P1 and P2 counts per version, as a table with a row per version and group, \
+                or as a JSON list of every bug with what we made of it.")
+            .takes_value(true)
+            .possible_values([
+                "text",
+                "json",
+                "yaml",
+                "prometheus",
+                "dot",
+                "oneline",
+                "table",
+                "bugs",
+            ])
+            .default_value("text"),
+    )
+    .arg(
+        Arg::new("with-summaries")
+            .long("with-summaries")
+            .help("Include each bug's summary with --format bugs")
+            .long_help("Include each bug's summary in the list from --format bugs, \
+                so it can be read as a triage worklist rather than just ids and counts."),
+    )
+    .arg(
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .help("Write the summary to this file instead of stdout")
+            .takes_value(true)
+            .value_name("FILE")
+            .global(true),
+    )
+    .arg(
+        Arg::new("no-color")
+            .long("no-color")
+            .help("Don't color the counts")
+            .long_help("Don't color the P1, P2, and landed counts. They're only colored when writing to a terminal, \
+                and NO_COLOR turns them off too."),
+    )
+    .arg(
+        Arg::new("snapshot-dir")
+            .long("snapshot-dir")
+            .help("Save this run's counts to a history file in this directory")
+            .long_help("Append this run's counts, with a timestamp, to snapshots.jsonl in this directory, \
+                so --trend can compare later runs against it.")
+            .takes_value(true)
+            .value_name("DIR"),
+    )
+    .arg(
+        Arg::new("trend")
+            .long("trend")
+            .help("Show how the counts changed since earlier snapshots")
+            .long_help("Show how the P1, P2, and total open counts changed since the last snapshot, \
+                and since the snapshot from --trend-days ago.")
+            .requires("snapshot-dir"),
+    )
+    .arg(
+        Arg::new("trend-days")
+            .long("trend-days")
+            .help("How far back --trend should look")
+            .takes_value(true)
+            .value_name("DAYS")
+            .default_value("7"),
+    )
+    .arg(
+        Arg::new("velocity")
+            .long("velocity")
+            .help("Show how many bugs land per week, and when each version would be done")
+            .long_help("Show how many bugs each version landed per week over the last --velocity-weeks of snapshots, \
+                and project when its open bugs would be done at that rate.")
+            .requires("snapshot-dir"),
+    )
+    .arg(
+        Arg::new("review-throughput")
+            .long("review-throughput")
+            .help("Show roughly how many bugs in review landed since the last snapshot")
+            .long_help("Show how many bugs went from in review to landed since the last snapshot, and how fast, \
+                to spot review bottlenecks. It's estimated from how the landed and in review counters changed, \
+                so bugs that went in and out of review between snapshots aren't seen.")
+            .requires("snapshot-dir"),
+    )
+    .arg(
+        Arg::new("velocity-weeks")
+            .long("velocity-weeks")
+            .help("How many weeks of snapshots --velocity should average over")
+            .takes_value(true)
+            .value_name("WEEKS")
+            .default_value("4"),
+    )
+    .arg(
+        Arg::new("by")
+            .long("by")
+            .help("How to group the bugs")
+            .long_help("Group each version's bugs by product (front-end or platform, or the groups from --group), \
+                or by assignee to see each person's workload.")
+            .takes_value(true)
+            .possible_values([
+                "product",
+                "assignee",
+            ])
+            .default_value("product"),
+    )
+    .arg(
+        Arg::new("since")
+            .long("since")
+            .help("Only count bugs changed after this date")
+            .long_help("Only count bugs whose last change was after this date, for \"what moved this week\" reports. \
+                Takes a date like 2020-10-01 (midnight UTC), a date and time like 2020-10-01T12:00:00 (UTC), \
+                or an RFC 3339 timestamp.")
+            .takes_value(true)
+            .value_name("DATE"),
+    )
+    .arg(
+        Arg::new("show-all")
+            .long("show-all")
+            .help("Show every group, even ones without open bugs")
+            .conflicts_with("min-open"),
+    )
+    .arg(
+        Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("Say which versions had no bugs, instead of leaving them out"),
+    )
+    .arg(
+        Arg::new("min-open")
+            .long("min-open")
+            .help("Only show groups with at least this many open bugs")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1"),
+    )
+    .arg(
+        Arg::new("total")
+            .long("total")
+            .help("Also show the totals for all versions")
+            .long_help("After the per-version summaries, show the totals across all versions and groups. \
+                Bugs tagged for more than one version are only counted once, for the earliest version."),
+    )
+    .arg(
+        Arg::new("sla")
+            .long("sla")
+            .help("List open bugs older than a severity's target, as SEVERITY=DAYS")
+            .long_help("List the open bugs of a severity that were filed more than this many days ago, \
+                with a count per severity and product, e.g. --sla S1=7 --sla S2=30. Can be given more than once.")
+            .takes_value(true)
+            .value_name("SEVERITY=DAYS")
+            .multiple_occurrences(true),
+    )
+    .arg(
+        Arg::new("ages")
+            .long("ages")
+            .help("Show how long the open bugs have been open")
+            .long_help("Show a histogram of how many open bugs were filed in the last week, month, or three months, \
+                or before that, for each version and group."),
+    )
+    .arg(
+        Arg::new("assigned-new")
+            .long("assigned-new")
+            .help("List the bugs that are assigned to someone, but still NEW")
+            .long_help("List the NEW and UNCONFIRMED bugs that are assigned to someone, \
+                who probably started on them without marking them ASSIGNED."),
+    )
+    .arg(
+        Arg::new("keyword")
+            .long("keyword")
+            .help("Only count bugs with one of these keywords, and show how many are open for each")
+            .long_help("Only count bugs with at least one of these Bugzilla keywords, e.g. --keyword regression,sec-high, \
+                and list how many bugs with each keyword are still open after the summary.")
+            .takes_value(true)
+            .value_name("KEYWORD")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true),
+    )
+    .arg(
+        Arg::new("blocked")
+            .long("blocked")
+            .help("List the open P1s that depend on other bugs")
+            .long_help("List the open P1s that depend on other bugs, and which bugs they're waiting on."),
+    )
+    .arg(
+        Arg::new("check-blockers")
+            .long("check-blockers")
+            .help("Look up the blockers, and only list the ones that are still open")
+            .requires("blocked"),
+    )
+    .arg(
+        Arg::new("group")
+            .long("group")
+            .help("Put a product's bugs in a group, as PRODUCT=GROUP")
+            .long_help("Put a product's bugs in a group when grouping by product, e.g. --group DevTools=DevTools. \
+                Can be given more than once. Replaces the default of Firefox and Toolkit in Front-end; \
+                products that aren't listed go in the --default-group.")
+            .takes_value(true)
+            .value_name("PRODUCT=GROUP")
+            .multiple_occurrences(true),
+    )
+    .arg(
+        Arg::new("default-group")
+            .long("default-group")
+            .help("The group for products without a --group")
+            .takes_value(true)
+            .value_name("GROUP")
+            .default_value("Platform"),
+    )
+    .arg(
+        Arg::new("products")
+            .long("products")
+            .help("Only count bugs in these products")
+            .takes_value(true)
+            .value_name("PRODUCT")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true),
+    )
+    .arg(
+        Arg::new("products-exclude")
+            .long("products-exclude")
+            .help("Don't count bugs in these products")
+            .long_help("Leave out the bugs in these products, e.g. --products-exclude Testing, \
+                without having to list every other product with --products.")
+            .takes_value(true)
+            .value_name("PRODUCT")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true),
+    )
+    .subcommand(
+        App::new("diff")
+            .about("Compare two saved summaries")
+            .long_about("Compare two summaries saved with --format json (or lines from a snapshots.jsonl), \
+                and print how each counter changed for every version and group.")
+            .arg(
+                Arg::new("old")
+                    .help("The earlier summary")
+                    .required(true)
+                    .value_name("OLD"),
+            )
+            .arg(
+                Arg::new("new")
+                    .help("The later summary")
+                    .required(true)
+                    .value_name("NEW"),
+            ),
+    )
+    .arg(bug_status::config::config_arg())
+    .arg(bug_status::http::dump_raw_arg())
+    .subcommand(bug_status::completions_command());
+    let matches = bug_status::config::get_matches(&app)?;
+    if let Some(dir) = matches.value_of("dump-raw") {
+        bug_status::http::dump_raw(dir)?;
+    }
+    match matches.subcommand() {
+        Some(("diff", matches)) => {
+            let mut out = bug_status::open_output(matches.value_of("output"))?;
+            return diff(&mut out, matches.value_of("old").unwrap(), matches.value_of("new").unwrap());
+        }
+        Some(("completions", matches)) => {
+            bug_status::print_completions(app, matches);
+            return Ok(());
+        }
+        _ => {}
+    }
+    let input = matches.value_of("input");
+    let max_age = matches.value_of("max-age").map(|days| days.parse::<i64>()).transpose()?;
+    let since = matches.value_of("since").map(parse_date).transpose()?;
+    let api_key = matches.value_of("api-key");
+
+    let by = matches.value_of("by").unwrap();
+    let groups = match matches.values_of("group") {
+        Some(values) => values.map(|value| {
+            value.split_once('=').ok_or_else(|| eyre!("--group should look like PRODUCT=GROUP, not {}", value))
+        }).collect::<Result<Vec<_>>>()?,
+        None => vec![("Firefox", "Front-end"), ("Toolkit", "Front-end")],
+    };
+    let default_group = matches.value_of("default-group").unwrap();
+    let products = matches.values_of("products").map(|values| values.collect::<Vec<_>>());
+    let excluded_products = matches.values_of("products-exclude").map_or(vec![], |values| values.collect::<Vec<_>>());
+    let wanted_keywords = matches.values_of("keyword").map(|values| values.collect::<Vec<_>>());
+    let servers = Servers::new(matches.value_of("bugzilla-url").unwrap(), DEFAULT_JIRA);
+
+    let components = matches.values_of("component").map_or(Ok(vec![]), |values| values.map(|value| {
+        value.split_once("::").ok_or_else(|| eyre!("--component should look like Product::Component, not {}", value))
+    }).collect::<Result<Vec<_>>>())?;
+    let saved_search = matches.value_of("saved-search");
+    let filters = match saved_search {
+        Some(name) => {
+            let mut filters = vec![("savedsearch".to_string(), name.to_string())];
+            if let Some(sharer) = matches.value_of("sharer-id") {
+                filters.push(("sharer_id".to_string(), sharer.to_string()));
+            }
+            filters
+        }
+        None => component_query(&components),
+    };
+    // Components and saved searches replace the whiteboard tags, unless the versions were asked for too.
+    let tagged = (components.is_empty() && saved_search.is_none())
+        || matches.occurrences_of("versions") > 0
+        || matches.occurrences_of("whiteboard") > 0;
+    let tags = if tagged {
+        matches.values_of("whiteboard").unwrap().collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+    let mut versions = if tagged {
+        matches.values_of("versions").unwrap().filter(|version| !version.trim().is_empty()).collect::<Vec<_>>()
+    } else {
+        vec![saved_search.unwrap_or("components")]
+    };
+    if versions.is_empty() {
+        app.clone().error(ErrorKind::EmptyValue, "There are no versions to summarize in --versions").exit();
+    }
+    versions.sort_by_cached_key(|version| version_key(version));
+    versions.dedup();
+    let (mut summary, counted): (Vec<Vec<State>>, Vec<_>) = versions.par_iter().map(|&version| {
+        let mut states = vec![];
+        if by == "product" {
+            for (_, group) in &groups {
+                get_state(&mut states, group, version);
+            }
+            get_state(&mut states, default_group, version);
+        }
+        let mut seen = vec![];
+
+        let bugs = get_bugs(version, &tags, &filters, input, &servers, api_key);
+        let fetched = bugs.len();
+        let mut counted = vec![];
 
         for bug in bugs {
             let id = bug["id"].as_i64().unwrap();
             if seen.contains(&id) {
-                println!("Duplicate bug!!!\n  {}\n  Bug {:?}\n", id, bug);
+                warn!("Duplicate bug {}: {:?}", id, bug);
             }
             seen.push(id);
-            match bug["product"].as_str().unwrap() {
-                "Toolkit" | "Firefox" => {
-                    categorize_bug(bug, &mut frontend_state);
-                },
-                _ => {
-                    categorize_bug(bug, &mut platform_state);
+            let product = bug["product"].as_str().unwrap();
+            if let Some(products) = &products {
+                if !products.contains(&product) {
+                    continue;
                 }
+            }
+            if excluded_products.contains(&product) {
+                continue;
+            }
+            if let Some(wanted) = &wanted_keywords {
+                if !keywords(&bug).iter().any(|keyword| wanted.iter().any(|x| x.eq_ignore_ascii_case(keyword))) {
+                    continue;
+                }
+            }
+            if let Some(since) = &since {
+                if !changed_since(&bug, since) {
+                    continue;
+                }
+            }
+            let group = match by {
+                "assignee" => assignee(&bug).unwrap_or("Unassigned"),
+                _ => groups.iter()
+                    .find(|(name, _)| *name == product)
+                    .map_or(default_group, |(_, group)| *group),
             };
+            let state = get_state(&mut states, group, version);
+            categorize_bug(&bug, state);
+            if let Some(max_age) = max_age {
+                check_age(&bug, state, max_age, &servers);
+            }
+            counted.push(bug);
+        }
+        if by == "assignee" {
+            states.sort_by(|a, b| a.name.cmp(&b.name));
         }
-        (frontend_state, platform_state)
-    }).collect();
+        for state in states.iter_mut() {
+            state.old_bugs.sort_by_key(|bug| -bug.age);
+        }
+        (states, (counted, fetched))
+    }).unzip();
+    let (counted, fetched): (Vec<Vec<Bug>>, Vec<usize>) = counted.into_iter().unzip();
+
+    let mut out = bug_status::open_output(matches.value_of("output"))?;
+    let min_open = if matches.is_present("show-all") {
+        0
+    } else {
+        matches.value_of("min-open").unwrap().parse::<usize>()?
+    };
+    let mut history = vec![];
+    if let Some(dir) = matches.value_of("snapshot-dir") {
+        let path = Path::new(dir).join("snapshots.jsonl");
+        history = read_snapshots(&path)?;
+        save_snapshot(&path, &summary)?;
+    }
+
+    let unknown_statuses = unknown_statuses(&summary);
+    if !matches!(matches.value_of("format"), None | Some("text")) {
+        for (status, ids) in &unknown_statuses {
+            warn!("Didn't count {} bugs with the unknown status {}", ids.len(), status);
+        }
+    }
 
-    for (frontend, platform) in summary {
-        if frontend.interesting() {
-            println!("{}", frontend);
+    match matches.value_of("format") {
+        Some("prometheus") => {
+            return print_prometheus(&mut out, &summary, by);
+        }
+        Some("json") => {
+            writeln!(out, "{}", serde_json::to_string_pretty(&to_snapshot(&summary))?)?;
+            return Ok(());
+        }
+        Some("yaml") => {
+            write!(out, "{}", serde_yaml::to_string(&to_snapshot(&summary))?)?;
+            return Ok(());
+        }
+        Some("bugs") => {
+            if !matches.is_present("with-summaries") {
+                for bug in summary.iter_mut().flatten().flat_map(|state| &mut state.bugs) {
+                    bug.summary = None;
+                }
+            }
+            let bugs = summary.iter().flatten().flat_map(|state| &state.bugs).collect::<Vec<_>>();
+            writeln!(out, "{}", serde_json::to_string_pretty(&bugs)?)?;
+            return Ok(());
+        }
+        Some("table") => {
+            return print_table(&mut out, &summary, by, min_open);
+        }
+        Some("dot") => {
+            return print_dot(&mut out, &unique_bugs(&counted));
+        }
+        Some("oneline") => {
+            // v82: 1 P1 / 0 P2 open (front-end), 0 P1 / 1 P2 open (platform)
+            for states in &summary {
+                let counts = states
+                    .iter()
+                    .filter(|state| state.interesting(min_open))
+                    .map(|state| format!("{} P1 / {} P2 open ({})", state.p1_open, state.p2_open, state.name.to_lowercase()))
+                    .collect::<Vec<_>>();
+                if !counts.is_empty() {
+                    writeln!(out, "{}: {}", version_label(&states[0].version), counts.join(", "))?;
+                }
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Colors are only for people watching a terminal, not for pipes or files.
+    let color = matches.value_of("output").is_none()
+        && io::stdout().is_terminal()
+        && !matches.is_present("no-color")
+        && env::var_os("NO_COLOR").is_none_or(|x| x.is_empty());
+    for (((states, bugs), fetched), version) in summary.iter().zip(&counted).zip(&fetched).zip(&versions) {
+        // Otherwise a tag with no bugs looks just like one where nothing's left to do.
+        if bugs.is_empty() && matches.is_present("verbose") {
+            if *fetched == 0 {
+                writeln!(out, "No bugs found for {}.", version_label(version))?;
+            } else {
+                writeln!(out, "None of the {} bugs for {} matched the filters.", fetched, version_label(version))?;
+            }
+            writeln!(out)?;
+            continue;
+        }
+        let mut interesting = false;
+        for state in states.iter().filter(|state| state.interesting(min_open)) {
+            if color {
+                writeln!(out, "{}", Colored(state))?;
+            } else {
+                writeln!(out, "{}", state)?;
+            }
+            interesting = true;
         }
-        if platform.interesting() {
-            println!("{}", platform);
+        if interesting {
+            writeln!(out)?;
         }
-        if frontend.interesting() || platform.interesting() {
-            println!();
+    }
+
+    if matches.is_present("total") {
+        let range = match versions.as_slice() {
+            [only] => only.to_string(),
+            [first, .., last] => format!("{}-{}", first, last),
+            [] => String::new(),
+        };
+        let mut total = State::new("Total", &range);
+        let mut seen = HashSet::new();
+        let mut duplicates = 0;
+        for bug in counted.iter().flatten() {
+            if seen.insert(bug["id"].as_i64().unwrap()) {
+                categorize_bug(bug, &mut total);
+            } else {
+                duplicates += 1;
+            }
+        }
+        if color {
+            writeln!(out, "{}", Colored(&total))?;
+        } else {
+            writeln!(out, "{}", total)?;
+        }
+        if duplicates > 0 {
+            writeln!(out, "    ({} bugs tagged for more than one version were only counted once.)", duplicates)?;
+        }
+        writeln!(out)?;
+    }
+
+    if matches.is_present("ages") {
+        let states = summary.iter().flatten().filter(|state| state.interesting(min_open)).collect::<Vec<_>>();
+        print_ages(&mut out, &states)?;
+        writeln!(out)?;
+    }
+
+    if let Some(targets) = matches.values_of("sla") {
+        let targets = targets.map(|value| {
+            let (severity, days) = value.split_once('=')
+                .ok_or_else(|| eyre!("--sla should look like SEVERITY=DAYS, not {}", value))?;
+            Ok((severity, days.parse::<i64>()?))
+        }).collect::<Result<Vec<_>>>()?;
+        let bugs = unique_bugs(&counted);
+        print_sla(&mut out, &bugs, &targets, &servers)?;
+        writeln!(out)?;
+    }
+
+    if matches.is_present("assigned-new") {
+        let bugs = unique_bugs(&counted);
+        print_assigned_new(&mut out, &bugs, &servers)?;
+        writeln!(out)?;
+    }
+
+    if let Some(wanted) = &wanted_keywords {
+        let bugs = unique_bugs(&counted);
+        print_keywords(&mut out, &bugs, wanted, &servers)?;
+        writeln!(out)?;
+    }
+
+    if matches.is_present("blocked") {
+        let bugs = unique_bugs(&counted);
+        let statuses = if matches.is_present("check-blockers") {
+            let mut ids = bugs.iter().flat_map(|bug| blockers(bug)).map(|id| id.to_string()).collect::<Vec<_>>();
+            ids.sort();
+            ids.dedup();
+            let mut statuses = HashMap::new();
+            for ids in ids.chunks(200) {
+                let params = [
+                    ("id".to_string(), ids.join(",")),
+                    ("include_fields".to_string(), "id,status".to_string()),
+                ];
+                for blocker in query_bugs(&params, "the blockers", &servers, api_key) {
+                    statuses.insert(blocker["id"].as_u64().unwrap(), blocker["status"].as_str().unwrap().to_string());
+                }
+            }
+            Some(statuses)
+        } else {
+            None
+        };
+        print_blocked(&mut out, &bugs, statuses.as_ref(), &servers)?;
+        writeln!(out)?;
+    }
+
+    if matches.is_present("trend") {
+        let states = summary.iter().flatten().collect::<Vec<_>>();
+        if let Some(last) = history.last() {
+            print_trend(&mut out, "Since the last snapshot", last, &states)?;
+        } else {
+            warn!("No snapshots yet, so there's no trend to show.");
+        }
+        let days = matches.value_of("trend-days").unwrap().parse::<i64>()?;
+        let cutoff = Utc::now() - Duration::days(days);
+        if let Some(old) = history.iter().rev().find(|x| x.time <= cutoff) {
+            writeln!(out)?;
+            print_trend(&mut out, &format!("Since {} days ago", days), old, &states)?;
+        }
+    }
+
+    if matches.is_present("velocity") {
+        if matches.is_present("trend") {
+            writeln!(out)?;
+        }
+        let states = summary.iter().flatten().collect::<Vec<_>>();
+        let weeks = matches.value_of("velocity-weeks").unwrap().parse::<i64>()?;
+        print_velocity(&mut out, &history, &states, weeks)?;
+    }
+
+    if matches.is_present("review-throughput") {
+        if matches.is_present("trend") || matches.is_present("velocity") {
+            writeln!(out)?;
+        }
+        let states = summary.iter().flatten().collect::<Vec<_>>();
+        match history.last() {
+            Some(last) => print_review_throughput(&mut out, last, &states)?,
+            None => warn!("No snapshots yet, so there's no review throughput to show."),
+        }
+    }
+
+    if !unknown_statuses.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "Unknown statuses, not counted:")?;
+        for (status, ids) in &unknown_statuses {
+            let ids = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+            writeln!(out, "  {}: {} bugs, {}/buglist.cgi?bug_id={}", status, ids.len(), servers.bugzilla, ids.join(","))?;
         }
     }
     Ok(())
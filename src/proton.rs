@@ -1,14 +1,30 @@
 use std::collections::HashMap;
 use std::env::var;
-use std::fs::{remove_file, File};
+use std::fs::{read, remove_file, File};
+use std::io::{BufWriter, Write};
+use std::ops::Deref;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
 
+use bug_status::http;
+use bug_status::mapping::{self, StatusMap};
+use bug_status::phabricator::{self, DEFAULT_PHABRICATOR};
+use bug_status::servers::{Servers, DEFAULT_BUGZILLA, DEFAULT_JIRA};
+use chrono::{DateTime, Duration, Utc};
 use clap::Arg;
-use color_eyre::eyre::{eyre, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use serde::Deserialize;
-use serde_json::{from_reader, to_writer_pretty, Map, Value};
+use rayon::slice::ParallelSlice;
+use rayon::ThreadPoolBuilder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_reader, json, to_string_pretty, to_writer_pretty, Map, Value};
+use tracing::{info, warn};
 
 #[macro_use]
 extern crate clap;
@@ -33,8 +49,77 @@ lazy_static! {
             usrname.unwrap()
         }
     };
+}
+
+#[derive(Clone, Debug)]
+struct Sprint {
+    name: String,
+    /** active, future, or closed. Empty when JIRA didn't tell us. */
+    state: String,
+}
+
+impl Sprint {
+    pub fn is_open(&self) -> bool {
+        self.state != "closed"
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state == "active"
+    }
+}
+
+#[derive(Clone, Debug)]
+struct JiraUser {
+    /** JIRA hides some people's email addresses, so this can be missing */
+    email: Option<String>,
+    account_id: String,
+    name: Option<String>,
+}
+
+impl JiraUser {
+    /**
+     * Is this user the one with this email address or account id, going by the --account-id ids for
+     * emails JIRA hides? None if JIRA hides theirs and we don't know the account id to check.
+     */
+    pub fn is(&self, identity: &str, account_ids: &[(&str, &str)]) -> Option<bool> {
+        let account_id = account_ids
+            .iter()
+            .find(|(email, _)| email.eq_ignore_ascii_case(identity))
+            .map(|(_, account_id)| *account_id);
+        if self.account_id == identity || account_id == Some(self.account_id.as_str()) {
+            return Some(true);
+        }
+        match &self.email {
+            Some(email) => Some(email == identity),
+            None => account_id.map(|_| false),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        self.name
+            .as_ref()
+            .or(self.email.as_ref())
+            .unwrap_or(&self.account_id)
+            .to_string()
+    }
+}
 
-    static ref PLUK: Option<String> = Some("pluk@mozilla.com".to_string());
+/** The ids of the custom fields we read, which differ between JIRA instances. */
+#[derive(Clone, Debug)]
+struct CustomFields {
+    epic: String,
+    points: String,
+    sprints: String,
+}
+
+impl CustomFields {
+    pub fn all(&self) -> [(&'static str, &str); 3] {
+        [
+            ("--epic-field", &self.epic),
+            ("--points-field", &self.points),
+            ("--sprint-field", &self.sprints),
+        ]
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -42,17 +127,18 @@ struct JiraIssue {
     /** The JIRA key, FIDEFE-123 */
     key: String,
     /** The link to this issue in JIRA */
+    #[allow(dead_code)]
     id: String,
-    assignee: Option<String>,
+    assignee: Option<JiraUser>,
     epic: Option<String>,
-    sprints: Vec<String>,
+    sprints: Vec<Sprint>,
     status: String,
-    points: Option<u64>,
+    points: Option<f64>,
     priority: String,
 }
 
 impl JiraIssue {
-    pub fn new(item: &Value) -> Self {
+    pub fn new(item: &Value, status_suffixes: &[&str], custom_fields: &CustomFields) -> Self {
         let key = item
             .get("key")
             .unwrap_or_else(|| panic!("Could not get key from {:?}", item))
@@ -74,21 +160,20 @@ impl JiraIssue {
             .unwrap_or_else(|| panic!("Could not get fields from {}", &key));
 
         let assignee = if let Some(assignee) = fields.get("assignee") {
-            if let Some(assignee) = assignee.as_object() {
-                if let Some(assignee) = assignee.get("emailAddress") {
-                    assignee.as_str().map(|x| x.to_string())
-                } else {
-                    None
+            assignee.as_object().map(|assignee| {
+                let get = |field| assignee.get(field).and_then(|x| x.as_str()).map(|x| x.to_string());
+                JiraUser {
+                    email: get("emailAddress"),
+                    account_id: get("accountId").unwrap_or_default(),
+                    name: get("displayName"),
                 }
-            } else {
-                None
-            }
+            })
         } else {
             None
         };
 
         let epic = fields
-            .get("customfield_10014")
+            .get(&custom_fields.epic)
             .unwrap_or(&Value::Null)
             .as_str()
             .map(|x| x.to_string());
@@ -104,21 +189,43 @@ impl JiraIssue {
             .as_str()
             .unwrap_or_else(|| panic!("Could not get status name from {}", &key))
             .to_string();
-        let status = status.replace(" (migrated)", "");
+        // Migrated projects can have statuses like "In Progress (migrated)".
+        let mut status = status.trim_end();
+        for suffix in status_suffixes {
+            if let Some(stripped) = status.strip_suffix(suffix) {
+                status = stripped.trim_end();
+            }
+        }
+        let status = status.to_string();
 
         let points = fields
-            .get("customfield_10037")
+            .get(&custom_fields.points)
             .unwrap_or(&Value::Null)
-            .as_f64()
-            .map(|x| x as u64);
+            .as_f64();
 
         let empty = vec![];
         let sprints = fields
-            .get("customfield_10020")
+            .get(&custom_fields.sprints)
             .unwrap_or(&Value::Null)
             .as_array()
             .unwrap_or(&empty)
-            .iter().map(|x| x.as_str().unwrap_or("???").to_owned()).collect::<Vec<_>>();
+            .iter()
+            .map(|sprint| {
+                // Sprints are usually objects, but older data has plain strings.
+                let name = sprint
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .or_else(|| sprint.as_str())
+                    .unwrap_or("???")
+                    .to_owned();
+                let state = sprint
+                    .get("state")
+                    .and_then(|state| state.as_str())
+                    .unwrap_or("")
+                    .to_lowercase();
+                Sprint { name, state }
+            })
+            .collect::<Vec<_>>();
 
         let prio = if let Some(prio) = fields.get("priority") {
             if let Some(prio) = prio.as_object() {
@@ -147,6 +254,48 @@ impl JiraIssue {
             priority,
         }
     }
+
+    /** Compare statuses ignoring case and stray whitespace, in case JIRA's names drift. */
+    pub fn has_status(&self, status: &str) -> bool {
+        self.status.trim().eq_ignore_ascii_case(status.trim())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        ["Closed", "Done", "Resolved"].iter().any(|status| self.has_status(status))
+    }
+
+    /** Whether the issue is assigned to this person, or None if we can't tell who JIRA has it assigned to. */
+    pub fn has_assignee(&self, assignee: &Option<String>, account_ids: &[(&str, &str)]) -> Option<bool> {
+        match (&self.assignee, assignee) {
+            (Some(user), Some(assignee)) => user.is(assignee, account_ids),
+            (None, None) => Some(true),
+            _ => Some(false),
+        }
+    }
+
+    pub fn assignee_label(&self) -> Option<String> {
+        self.assignee.as_ref().map(|user| user.label())
+    }
+
+    /** Describe this issue's epic, if we know its name. */
+    pub fn epic_label(&self, epics: &HashMap<String, Epic>) -> String {
+        if let Some(key) = &self.epic {
+            if let Some(epic) = epics.get(key) {
+                return format!(" belongs to epic {} ({})", key, epic.name);
+            }
+        }
+        "".to_string()
+    }
+}
+
+/** What we looked up about an epic for --epic-names and --check-epics. */
+#[derive(Clone, Debug)]
+struct Epic {
+    name: String,
+    /** The epic's JIRA status, empty if we only know its name */
+    status: String,
+    /** Whether the status is in JIRA's Done category */
+    done: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -154,81 +303,389 @@ struct BugzillaJiraLink {
     bugzilla: String,
     jira: JiraIssue,
     cached: bool,
+    /** Any other bugs the issue links to, which we don't check */
+    others: Vec<String>,
 }
 
 impl BugzillaJiraLink {
-    pub fn new(jira: JiraIssue, cached_data: &Map<String, Value>) -> Option<Self> {
-        let (bugzilla, cached) = if let Some(data) = cached_data.get(&jira.key) {
-            (data.as_str().unwrap().to_owned(), true)
+    pub fn new(jira: JiraIssue, cached_data: &Map<String, Value>, servers: &Servers) -> Result<Option<Self>> {
+        let (bugzilla, cached, others) = if let Some(data) = cached_data.get(&jira.key) {
+            (data.as_str().unwrap().to_owned(), true, vec![])
         } else {
             let link = format!(
-                "https://mozilla-hub.atlassian.net/rest/api/3/issue/{}/remotelink",
-                &jira.key
+                "{}/rest/api/3/issue/{}/remotelink",
+                servers.jira, &jira.key
             );
-            let resp: Vec<HashMap<String, Value>> = get_link(&link, true).unwrap();
-            if resp.is_empty() {
-                println!("No link for https://mozilla-hub.atlassian.net/browse/{}", &jira.key);
-                return None
+            let resp: Vec<HashMap<String, Value>> = get_link(&link, true)
+                .wrap_err_with(|| format!("Could not get the links for {}", jira.key))?;
+            // Issues can link to several things, only some of which are bugs.
+            let prefix = servers.bug_url("");
+            let mut bugzillas: Vec<String> = vec![];
+            for remote in &resp {
+                let id = remote
+                    .get("object")
+                    .and_then(|object| object.get("url"))
+                    .and_then(|url| url.as_str())
+                    .and_then(|url| url.strip_prefix(&prefix));
+                if let Some(id) = id {
+                    if !bugzillas.iter().any(|x| x == id) {
+                        bugzillas.push(id.to_string());
+                    }
+                }
             }
-            let data = resp[0]["object"]
-                .as_object()
-                .unwrap_or_else(|| panic!("Could not get object from {}", link));
-
-            (
-                data["url"]
-                    .as_str()
-                    .unwrap_or_else(|| panic!("Could not get url from {}", link))
-                    .replace("https://bugzilla.mozilla.org/show_bug.cgi?id=", ""),
-                false,
-            )
+            if bugzillas.is_empty() {
+                return Ok(None);
+            }
+            let bugzilla = bugzillas.remove(0);
+            (bugzilla, false, bugzillas)
         };
-        Some(Self {
+        Ok(Some(Self {
             bugzilla,
             jira,
             cached,
-        })
+            others,
+        }))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BrokenLink {
+    /** The JIRA key whose link didn't lead to a bug */
+    key: String,
+    /** Why we couldn't use the link */
+    reason: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Change {
+    /** What kind of discrepancy this is, e.g. "changed_status" */
+    kind: &'static str,
+    /** The Bugzilla bug id, if the issue has a usable link */
+    bug: Option<String>,
+    /** The JIRA key, FIDEFE-123 */
+    jira: String,
+    field: &'static str,
+    old: Value,
+    new: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Change {
+    pub fn new(kind: &'static str, field: &'static str, bug: &BugzillaBug, old: Value, new: Value) -> Self {
+        Self {
+            kind,
+            bug: Some(bug.id.clone()),
+            jira: bug.jira.key.clone(),
+            field,
+            old,
+            new,
+            detail: None,
+        }
+    }
+}
+
+/** Collects the changes we need, printing them as we go for the text format. */
+struct Report {
+    format: String,
+    section: String,
+    changes: Vec<Change>,
+    /** Issues for people to look into, like ones without a usable link, which aren't changes we can name */
+    notes: Vec<Change>,
+    /** How many changes each section has, in the order they came in */
+    tallies: Vec<(String, usize)>,
+    /** Each section's title and the lines we added to it, for the Slack summary */
+    lines: Vec<(String, Vec<String>)>,
+    /** Where the report goes, stdout or the --output file */
+    out: Box<dyn Write>,
+    /** The GitHub workflow command to annotate changes with, error when they fail the run */
+    annotation: &'static str,
+    /** The fields of the changes to report, from --only-categories, or None for all of them */
+    only: Option<Vec<&'static str>>,
+}
+
+/** Escape a GitHub workflow command's message, or a property value when `property` is set. */
+fn escape_workflow(value: &str, property: bool) -> String {
+    let value = value.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    if property {
+        value.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        value
+    }
+}
+
+impl Report {
+    pub fn new(format: &str, fail_on_changes: bool, out: Box<dyn Write>) -> Self {
+        Self {
+            format: format.to_string(),
+            section: "".to_string(),
+            changes: vec![],
+            notes: vec![],
+            tallies: vec![],
+            lines: vec![],
+            out,
+            annotation: if fail_on_changes { "error" } else { "warning" },
+            only: None,
+        }
+    }
+
+    /** Whether to report changes to this field, e.g. "points". */
+    pub fn wants(&self, field: &str) -> bool {
+        self.only.as_ref().is_none_or(|only| only.contains(&field))
+    }
+
+    pub fn section(&mut self, title: &str) -> Result<()> {
+        if self.format == "text" {
+            writeln!(self.out, "\n\n{}", title)?;
+        }
+        self.section = title.to_string();
+        Ok(())
+    }
+
+    pub fn text(&mut self, line: String) -> Result<()> {
+        if self.format == "text" {
+            writeln!(self.out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, section: &str, change: Change, line: String) -> Result<()> {
+        if !self.wants(change.field) {
+            return Ok(());
+        }
+        self.show(section, line, self.annotation)?;
+        self.changes.push(change);
+        match self.tallies.iter_mut().find(|(title, _)| title == section) {
+            Some((_, count)) => *count += 1,
+            None => self.tallies.push((section.to_string(), 1)),
+        }
+        Ok(())
+    }
+
+    /**
+     * Like add, but for something to look into rather than a change to make,
+     * so it doesn't count towards the tally, --fail-on-changes, or posting to Slack.
+     */
+    pub fn note(&mut self, section: &str, change: Change, line: String) -> Result<()> {
+        if !self.wants(change.field) {
+            return Ok(());
+        }
+        self.show(section, line, "notice")?;
+        self.notes.push(change);
+        Ok(())
+    }
+
+    fn show(&mut self, section: &str, line: String, annotation: &str) -> Result<()> {
+        if self.section != section {
+            self.section(section)?;
+        }
+        if self.format == "github" {
+            writeln!(self.out, "::{} title={}::{}",
+                annotation,
+                escape_workflow(section.trim_end_matches(':'), true),
+                escape_workflow(line.trim(), false))?;
+        }
+        match self.lines.last_mut() {
+            Some((title, lines)) if title == section => lines.push(line.trim().to_string()),
+            _ => self.lines.push((section.to_string(), vec![line.trim().to_string()])),
+        }
+        self.text(line)
+    }
+
+    /** A Slack message with the number of changes in each section, and the first few of each. */
+    pub fn slack_summary(&self) -> String {
+        if !self.need_changes() {
+            return "No changes necessary! 🎉".to_string();
+        }
+        let mut message = format!("{} changes needed in JIRA:", self.changes.len());
+        for (title, lines) in &self.lines {
+            message.push_str(&format!("\n\n*{}* {}", title.trim_end_matches(':'), lines.len()));
+            for line in lines.iter().take(3) {
+                message.push_str(&format!("\n• {}", line));
+            }
+            if lines.len() > 3 {
+                message.push_str(&format!("\n…and {} more", lines.len() - 3));
+            }
+        }
+        message
+    }
+
+    pub fn need_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    /** How many changes there are in each section, like "3 Changed status, 2 Changed assignees — 5 changes needed". */
+    pub fn tally(&self) -> String {
+        let counts = self.tallies
+            .iter()
+            .map(|(title, count)| format!("{} {}", count, title.trim_end_matches(':')))
+            .collect::<Vec<_>>();
+        format!("{} — {} changes needed", counts.join(", "), self.changes.len())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        match self.format.as_str() {
+            "json" => {
+                let all = self.changes.iter().chain(&self.notes).collect::<Vec<_>>();
+                writeln!(self.out, "{}", to_string_pretty(&all)?)?
+            }
+            "github" => {
+                if self.need_changes() {
+                    writeln!(self.out, "::notice::{}", escape_workflow(&self.tally(), false))?;
+                } else {
+                    writeln!(self.out, "::notice::No changes necessary!")?;
+                }
+            }
+            _ => {
+                if self.need_changes() {
+                    writeln!(self.out, "\n\n{}", self.tally())?;
+                } else {
+                    writeln!(self.out, "\n\nNo changes necessary! 🎉\n")?;
+                }
+            }
+        }
+        Ok(())
     }
 }
 
+/** A bug's fields as we last fetched them, for the --bug-cache-ttl cache. */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CachedBug {
+    fetched: DateTime<Utc>,
+    /** None for a restricted bug */
+    bug: Option<BugFields>,
+}
+
+/**
+ * Which file to keep a cache in: `name`, or `name.gz` with --compress-cache.
+ * Returns the file to write, and the one to read, which is the other kind if only that one exists yet.
+ */
+fn cache_paths(name: &str, compress: bool) -> (String, Option<String>) {
+    let (wanted, other) = if compress { (format!("{}.gz", name), name.to_string()) } else { (name.to_string(), format!("{}.gz", name)) };
+    let existing = [&wanted, &other].iter().find(|path| Path::new(path).is_file()).map(|path| path.to_string());
+    (wanted, existing)
+}
+
+/** Read a cache, telling gzipped files from plain JSON by their first bytes rather than their name. */
+fn read_cache<T: DeserializeOwned>(path: &str) -> Result<T> {
+    let data = read(path)?;
+    let parsed = if data.starts_with(&[0x1f, 0x8b]) {
+        from_reader(GzDecoder::new(data.as_slice()))
+    } else {
+        serde_json::from_slice(&data)
+    };
+    parsed.map_err(|err| eyre!("Could not parse {}: {}", path, err))
+}
+
+/** Write a cache, gzipped and compact if the path ends in .gz, and remove the other kind so it can't go stale. */
+fn write_cache<T: Serialize>(path: &str, value: &T) -> Result<()> {
+    let other = match path.strip_suffix(".gz") {
+        Some(plain) => {
+            let mut file = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+            serde_json::to_writer(&mut file, value)?;
+            file.finish()?.flush()?;
+            plain.to_string()
+        }
+        None => {
+            to_writer_pretty(File::create(path)?, value)?;
+            format!("{}.gz", path)
+        }
+    };
+    if Path::new(&other).is_file() {
+        remove_file(other)?;
+    }
+    Ok(())
+}
+
+/** The bug statuses we've already fetched, and how long to trust them without asking Bugzilla. */
+struct BugCache {
+    bugs: HashMap<String, CachedBug>,
+    ttl: Duration,
+}
+
 #[derive(Clone, Debug)]
 struct BugzillaBug {
     id: String,
+    summary: String,
     status: String,
-    points: Option<u64>,
+    product: String,
+    points: Option<f64>,
     assignee: Option<String>,
-    has_patch: bool,
+    /** The assignee's Bugzilla real name, for showing people rather than emails. */
+    assignee_name: Option<String>,
+    /** Non-obsolete Phabricator requests attached to the bug. */
+    patch_count: usize,
+    /** The Phabricator revisions behind those requests, 12345 for D12345 */
+    revisions: Vec<u64>,
     priority: String,
+    /** The bug this one was closed as a duplicate of, where the work really happens */
+    dupe_of: Option<u64>,
     jira: JiraIssue,
 }
 
-impl BugzillaBug {
-    pub fn new(link: BugzillaJiraLink, bz_statuses: &HashMap<String, Map<String, Value>>) -> Self {
-        let id = link.bugzilla;
-        let bz_data = bz_statuses.get(&id)
-            .unwrap_or_else(|| panic!("Could not find link for {:?}", id));
-        let status = bz_data
-            .get("status")
-            .unwrap_or_else(|| panic!("Could not get status from {:?}", bz_data))
-            .as_str()
-            .unwrap_or_else(|| panic!("Could not get status from {:?}", bz_data))
+/**
+ * The fields of a bug that we use, pulled out of Bugzilla's JSON when we fetch it
+ * so we don't hold on to the whole thing.
+ */
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BugFields {
+    summary: String,
+    status: String,
+    product: String,
+    points: Option<f64>,
+    priority: String,
+    assignee: Option<String>,
+    assignee_name: Option<String>,
+    /** Non-obsolete Phabricator requests attached to the bug. */
+    patch_count: usize,
+    /** The Phabricator revisions behind those requests, 12345 for D12345 */
+    revisions: Vec<u64>,
+    dupe_of: Option<u64>,
+    /** When the bug last changed, to tell whether a cached copy is still good */
+    last_change_time: Option<String>,
+}
+
+/**
+ * Points from Bugzilla's text field, which can be fractional like "1.5", or blank or "---" for untriaged bugs,
+ * so anything that isn't a number is no points rather than an error.
+ */
+fn parse_points(points: &str) -> Option<f64> {
+    points.trim().parse::<f64>().ok().filter(|points| points.is_finite())
+}
+
+/** Points for the JSON report, keeping whole numbers whole, e.g. 5 rather than 5.0. */
+fn points_value(points: Option<f64>) -> Value {
+    match points {
+        Some(points) if points.fract() == 0.0 && points.abs() < i64::MAX as f64 => Value::from(points as i64),
+        Some(points) => Value::from(points),
+        None => Value::Null,
+    }
+}
+
+impl BugFields {
+    /** The fields we use from Bugzilla's JSON, or None for security bugs we can't fully see. */
+    pub fn from_json(bz_data: &Map<String, Value>) -> Option<Self> {
+        let status = bz_data.get("status")?.as_str()?.to_string();
+        let priority = bz_data.get("priority")?.as_str()?.to_string();
+        let summary = bz_data
+            .get("summary")
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
             .to_string();
-        let points = bz_data
-            .get("cf_fx_points")
-            .unwrap_or_else(|| panic!("Could not get points from {:?}", bz_data))
-            .as_str()
-            .unwrap_or_else(|| panic!("Could not get points from {:?}", bz_data))
-            .parse::<u64>()
-            .ok();
-        let priority = bz_data
-            .get("priority")
-            .unwrap_or_else(|| panic!("Could not get priority from {:?}", bz_data))
-            .as_str()
-            .unwrap_or_else(|| panic!("Could not get priority from {:?}", bz_data))
+        let product = bz_data
+            .get("product")
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
             .to_string();
+        let points = match bz_data.get("cf_fx_points") {
+            Some(Value::String(points)) => parse_points(points),
+            Some(Value::Number(points)) => points.as_f64(),
+            _ => None,
+        };
 
-        let has_patch = if let Some(attachments) = bz_data.get("attachments") {
+        let patch_count = if let Some(attachments) = bz_data.get("attachments") {
             if let Some(attachments) = attachments.as_array() {
-                attachments.iter().any(|attachment| {
+                attachments.iter().filter(|attachment| {
                     if let Some(attachment) = attachment.as_object() {
                         let is_obsolete = if let Some(obsolete) = attachment.get("is_obsolete") {
                             obsolete.as_u64().unwrap_or_default() == 1
@@ -248,248 +705,908 @@ impl BugzillaBug {
                     } else {
                         false
                     }
-                })
+                }).count()
             } else {
-                false
+                0
             }
         } else {
-            false
+            0
         };
 
-        let mut assignee = if let Some(assignee) = bz_data.get("assigned_to") {
+        let revisions = bz_data
+            .get("attachments")
+            .and_then(|x| x.as_array())
+            .map(|attachments| {
+                attachments
+                    .iter()
+                    .filter(|attachment| attachment.get("is_obsolete").and_then(|x| x.as_u64()).unwrap_or_default() != 1)
+                    .filter_map(|attachment| attachment.get("file_name").and_then(|x| x.as_str()))
+                    .filter_map(phabricator::revision_id)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Placeholder accounts like nobody@mozilla.org are cleared later, by clear_placeholder_assignee.
+        let assignee = if let Some(assignee) = bz_data.get("assigned_to") {
             assignee.as_str().map(|x| x.to_string())
         } else {
             None
         };
-        if assignee == Some("nobody@mozilla.org".to_string()) {
-            assignee = None;
-        }
+        let dupe_of = if bz_data.get("resolution").and_then(|x| x.as_str()) == Some("DUPLICATE") {
+            bz_data.get("dupe_of").and_then(|x| x.as_u64())
+        } else {
+            None
+        };
 
-        let jira = link.jira;
-        Self {
-            id,
+        let assignee_name = bz_data
+            .get("assigned_to_detail")
+            .and_then(|x| x.get("real_name"))
+            .and_then(|x| x.as_str())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string());
+
+        let last_change_time = bz_data
+            .get("last_change_time")
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_string());
+
+        Some(Self {
+            summary,
             status,
+            product,
             points,
-            assignee,
-            has_patch,
-            jira,
             priority,
+            assignee,
+            assignee_name,
+            patch_count,
+            revisions,
+            dupe_of,
+            last_change_time,
+        })
+    }
+}
+
+impl BugzillaBug {
+    pub fn new(link: BugzillaJiraLink, bug: &BugFields) -> Self {
+        let bug = bug.clone();
+        Self {
+            id: link.bugzilla,
+            summary: bug.summary,
+            status: bug.status,
+            product: bug.product,
+            points: bug.points,
+            assignee: bug.assignee,
+            assignee_name: bug.assignee_name,
+            patch_count: bug.patch_count,
+            revisions: bug.revisions,
+            jira: link.jira,
+            priority: bug.priority,
+            dupe_of: bug.dupe_of,
         }
     }
 
-    pub fn get_jira_status(&self) -> String {
-        match self.status.as_str() {
-            "ASSIGNED" => {
-                if self.has_patch {
-                    "In Review".to_string()
-                } else {
-                    "In Progress".to_string()
-                }
+    /** Treat bugs assigned to one of the `--unassigned` accounts as unassigned. */
+    pub fn clear_placeholder_assignee(&mut self, placeholders: &[&str]) {
+        if let Some(assignee) = &self.assignee {
+            if placeholders.iter().any(|x| x.eq_ignore_ascii_case(assignee)) {
+                self.assignee = None;
+                self.assignee_name = None;
             }
-            "NEW" | "UNCONFIRMED" => "Open".to_string(),
-            "REOPENED" => "Reopened".to_string(),
-            "RESOLVED" => "Closed".to_string(),
-            _ => self.status.clone(),
         }
     }
 
-    pub fn get_jira_priority(&self) -> String {
-        match self.priority.as_str() {
-            "P1" => "1".to_string(),
-            "P2" => "2".to_string(),
-            "P3" => "3".to_string(),
-            "P4" => "4".to_string(),
-            "P5" => "5".to_string(),
-            "--" => "10000".to_string(),
-            _ => self.priority.clone(),
-        }
-    }
-
-    pub fn get_jira_assignee(&self) -> Option<String> {
-        let assignee = self.assignee.as_ref()?;
-        // Some employees use other addresses in bugzilla.
-        match assignee.as_str() {
-            "agi@sferro.dev" => Some("asferro@mozilla.com".to_string()),
-            "andrei.br92@gmail.com" => Some("aoprea@mozilla.com".to_string()),
-            "bob.silverberg@gmail.com" => Some("bsilverberg@mozilla.com".to_string()),
-            "dao+bmo@mozilla.com" => Some("dgottwald@mozilla.com".to_string()),
-            "edilee@mozilla.com" => Some("elee@mozilla.com".to_string()),
-            "eitan@monotonous.org" => Some("eisaacson@mozilla.com".to_string()),
-            "emilio@crisal.io" => Some("ealvarez@mozilla.com".to_string()),
-            "enndeakin@gmail.com" => Some("neil@mozilla.com".to_string()),
-            "gijskruitbosch+bugs@gmail.com" => Some("gkruitbosch@mozilla.com".to_string()),
-            "gl@mozilla.com" => Some("gluong@mozilla.com".to_string()),
-            "jaws@mozilla.com" => Some("jwein@mozilla.com".to_string()),
-            "jfkthame@gmail.com" => Some("jkew@mozilla.com".to_string()),
-            "mixedpuppy@gmail.com" => Some("scaraveo@mozilla.com".to_string()),
-            "mozilla@kaply.com" => Some("mkaply@mozilla.com".to_string()),
-            "pbz@mozilla.com" => Some("pzuhlcke@mozilla.com".to_string()),
-            "rob@robwu.nl" => Some("rwu@mozilla.com".to_string()),
-            "tnikkel@gmail.com" => Some("tnikkel@mozilla.com".to_string()),
-            "tomica@gmail.com" => Some("tjovanovic@mozilla.com".to_string()),
-            // "" => Some("@mozilla.com".to_string()),
-
-            // Anyone else at Mozilla just gets their address.
-            x if x.ends_with("@mozilla.com") => Some(x.to_string()),
-            // External contributors get mapped to pluk!
-            _ => PLUK.clone(),
+    /** Who the bug is assigned to, by name if Bugzilla knows it, or else by email. */
+    pub fn assignee_label(&self) -> Option<String> {
+        self.assignee_name.clone().or_else(|| self.assignee.clone())
+    }
+
+    /** The bug's summary, quoted, to follow its URL in the report. */
+    pub fn summary_label(&self) -> String {
+        if self.summary.is_empty() {
+            "".to_string()
+        } else {
+            format!(" {:?}", self.summary)
         }
     }
+
+    /** Whether the issue's status already matches the bug's, including custom ones from --status-map. */
+    pub fn has_jira_status(&self, status_map: &StatusMap) -> bool {
+        status_map.is_consistent(&self.jira.status, &self.status, self.patch_count > 0)
+    }
+
+    pub fn get_jira_status(&self) -> String {
+        mapping::jira_status(&self.status, self.patch_count > 0)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        ["RESOLVED", "VERIFIED", "CLOSED"].contains(&self.status.as_str())
+    }
+
+    pub fn get_jira_priority(&self) -> String {
+        mapping::jira_priority(&self.priority)
+    }
+
+    /** The JIRA assignee, with external contributors going to their product's --product-assignee, or else the default. */
+    pub fn get_jira_assignee(&self, product_assignees: &[(&str, &str)], default_assignee: Option<&str>) -> Option<String> {
+        let default_assignee = product_assignees
+            .iter()
+            .find(|(product, _)| product.eq_ignore_ascii_case(&self.product))
+            .map(|(_, assignee)| *assignee)
+            .or(default_assignee);
+        mapping::jira_assignee(self.assignee.as_ref()?, default_assignee)
+    }
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    bug_status::load_dotenv();
+    bug_status::init_logging();
+
+    let app = app_from_crate!("\n")
+    .name("proton")
+    .about("Check that JIRA issues match their Bugzilla bugs")
+    .long_about("Check that each JIRA issue in a project matches the Bugzilla bug it links to, \
+        and report the points, statuses, assignees, priorities, epics, and sprints that need changing. \
+        Needs JIRA_USERNAME and JIRA_PASSWORD in the environment or a .env file.")
+    .after_long_help("EXAMPLES:
+    Check the Proton issues:
+        proton
+
+    Check another JIRA project, as JSON:
+        proton --jira-project FIDEFE --format json
 
-    let matches = app_from_crate!("\n")
+    Check a few issues from a custom query:
+        proton --jql 'project = FIDEFE AND sprint in openSprints()' --limit 20
+
+    Replay a captured set of issues and bugs, without credentials:
+        proton --json-input dataset.json
+
+    Fail a GitHub Actions job when anything is out of sync:
+        proton --format github --fail-on-changes")
     .arg(
         Arg::new("project")
             .short('p')
             .long("project")
-            .about("Which project to use")
-            .long_about("Specify a project to gather data on.")
+            .help("Which project to use")
+            .long_help("Specify a project to gather data on.")
             .takes_value(true)
-            .possible_values(&[
+            .possible_values([
                 "proton",
                 "mv3",
                 "mr22",
             ])
             .default_value("proton"),
     )
-    .get_matches();
+    .arg(
+        Arg::new("bugzilla-url")
+            .long("bugzilla-url")
+            .help("The Bugzilla instance to use")
+            .takes_value(true)
+            .value_name("URL")
+            .env("BUGZILLA_URL")
+            .default_value(DEFAULT_BUGZILLA),
+    )
+    .arg(
+        Arg::new("jira-url")
+            .long("jira-url")
+            .help("The JIRA instance to use")
+            .takes_value(true)
+            .value_name("URL")
+            .env("JIRA_URL")
+            .default_value(DEFAULT_JIRA),
+    )
+    .arg(
+        Arg::new("jira-project")
+            .long("jira-project")
+            .help("Use all the open issues in this JIRA project")
+            .long_help("Gather data on every open, non-epic issue in the JIRA project with this key, instead of a --project preset.")
+            .takes_value(true)
+            .value_name("KEY"),
+    )
+    .arg(
+        Arg::new("jql")
+            .long("jql")
+            .help("Use the JIRA issues matching this query")
+            .long_help("Gather data on the JIRA issues matching this JQL query. Overrides --project and --jira-project.")
+            .takes_value(true)
+            .value_name("QUERY"),
+    )
+    .arg(
+        Arg::new("check-done")
+            .long("check-done")
+            .help("Also check the issues marked Done in the last DAYS days")
+            .long_help("Also look at the issues that moved to a Done status in the last DAYS days, \
+                and list the ones whose bugs are still open under Closed in JIRA but open in Bugzilla, \
+                and the ones still in an active sprint. \
+                Only works with --project or --jira-project, not --jql.")
+            .takes_value(true)
+            .value_name("DAYS")
+            .conflicts_with("jql"),
+    )
+    .arg(
+        Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .help("How many JIRA requests to make at once")
+            .long_help("Limit how many remote links we fetch from JIRA in parallel. Defaults to one per CPU.")
+            .takes_value(true)
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .value_name("N"),
+    )
+    .arg(
+        Arg::new("show-requests")
+            .long("show-requests")
+            .help("Show the requests in flight under the progress bars")
+            .long_help("Show how many requests are waiting on JIRA or Bugzilla, and how long the last one took, \
+                under each progress bar, to see whether we're being throttled or are stuck."),
+    )
+    .arg(
+        Arg::new("bug-cache-ttl")
+            .long("bug-cache-ttl")
+            .help("Cache bug statuses between runs, for this many minutes")
+            .long_help("Keep the bugs we fetch in bugs.cache. Bugs fetched less than this many minutes ago are used \
+                as they are; older ones are only fetched again if their last change time has moved.")
+            .takes_value(true)
+            .value_name("MINUTES"),
+    )
+    .arg(
+        Arg::new("compress-cache")
+            .long("compress-cache")
+            .help("Keep the caches gzipped, as jira.cache.gz and bugs.cache.gz")
+            .long_help("Write the caches as gzipped JSON in jira.cache.gz and bugs.cache.gz, which are much smaller \
+                and quicker to load for big projects. Either kind of cache is read, so this can be turned on or off at any time."),
+    )
+    .arg(
+        Arg::new("no-cache")
+            .long("no-cache")
+            .help("Fetch everything fresh, without reading or writing the caches")
+            .long_help("Ignore jira.cache and bugs.cache for this run: fetch every link and bug fresh, \
+                and leave the cache files on disk untouched."),
+    )
+    .arg(
+        Arg::new("limit")
+            .long("limit")
+            .help("Only look at the first N issues")
+            .long_help("Only look at the first N JIRA issues, for a quick test run.")
+            .takes_value(true)
+            .value_name("N"),
+    )
+    .arg(
+        Arg::new("json-input")
+            .long("json-input")
+            .help("Check a captured set of issues and bugs instead of asking JIRA and Bugzilla")
+            .long_help("Check the issues and bugs in this JSON file instead of asking JIRA and Bugzilla, \
+                for reproducible runs without credentials. It has the JIRA search API's \"issues\", \
+                \"links\" from each issue's key to its bug id, the Bugzilla REST API's \"bugs\", \
+                and optionally \"epics\" from each epic's key to its name. \
+                The caches and Phabricator aren't used.")
+            .takes_value(true)
+            .value_name("FILE"),
+    )
+    .arg(
+        Arg::new("only-categories")
+            .long("only-categories")
+            .help("Only report these kinds of changes, e.g. epics,sprints")
+            .long_help("Only report these kinds of changes, as a comma-separated list, \
+                e.g. --only-categories epics,sprints when cleaning up epics. Defaults to all of them.")
+            .takes_value(true)
+            .value_name("CATEGORY")
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .possible_values(CATEGORIES.map(|(name, _)| name)),
+    )
+    .arg(
+        Arg::new("format")
+            .short('f')
+            .long("format")
+            .help("How to print the report")
+            .long_help("Print the report as text for people, as a JSON list of changes for scripts, \
+                or as GitHub Actions workflow commands so each change shows up as an annotation. \
+                With --fail-on-changes the annotations are errors, otherwise warnings.")
+            .takes_value(true)
+            .possible_values([
+                "text",
+                "json",
+                "github",
+            ])
+            .default_value("text"),
+    )
+    .arg(
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .help("Write the report to this file instead of stdout")
+            .takes_value(true)
+            .value_name("FILE"),
+    )
+    .arg(
+        Arg::new("status-suffix")
+            .long("status-suffix")
+            .help("Ignore this suffix on JIRA status names")
+            .long_help("Strip this suffix from JIRA status names before comparing them, \
+                e.g. \"(migrated)\" turns \"In Progress (migrated)\" into \"In Progress\". \
+                Can be given more than once.")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("SUFFIX")
+            .default_value("(migrated)"),
+    )
+    .arg(
+        Arg::new("status-map")
+            .long("status-map")
+            .help("Treat this JIRA status as matching this Bugzilla one")
+            .long_help("Accept a custom JIRA workflow status for bugs with the given Bugzilla status, \
+                e.g. --status-map \"Ready for QA=RESOLVED\", instead of reporting that it should change. \
+                Can be given more than once.")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("JIRA=BUGZILLA"),
+    )
+    .arg(
+        Arg::new("default-assignee")
+            .long("default-assignee")
+            .help("Who to assign external contributors' bugs to in JIRA")
+            .long_help("The JIRA user to assign bugs to when the Bugzilla assignee isn't a Mozilla employee. \
+                By default those bugs are left unassigned in JIRA.")
+            .takes_value(true)
+            .value_name("EMAIL")
+            .env("JIRA_DEFAULT_ASSIGNEE"),
+    )
+    .arg(
+        Arg::new("product-assignee")
+            .long("product-assignee")
+            .help("Who to assign external contributors' bugs in this product to, as PRODUCT=EMAIL")
+            .long_help("The JIRA user to assign a product's bugs to when the Bugzilla assignee isn't a Mozilla employee, \
+                like the owning team's triage lead, e.g. --product-assignee Toolkit=someone@mozilla.com. \
+                Products that aren't given use --default-assignee. Can be given more than once.")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("PRODUCT=EMAIL"),
+    )
+    .arg(
+        Arg::new("account-id")
+            .long("account-id")
+            .help("The JIRA account id for this email, as EMAIL=ACCOUNT_ID")
+            .long_help("The JIRA account id of someone whose email address JIRA hides, \
+                e.g. --account-id someone@mozilla.com=557058:f58131cb-b67d-43c7-b30d-6b58d40bd077, \
+                so we can check the issues assigned to them. Issues assigned to people with hidden emails \
+                and no --account-id are reported as unknown assignees. Can be given more than once.")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("EMAIL=ACCOUNT_ID"),
+    )
+    .arg(
+        Arg::new("unassigned")
+            .long("unassigned")
+            .help("Treat bugs assigned to this account as unassigned")
+            .long_help("Treat bugs assigned to this Bugzilla account as unassigned, e.g. a triage bot or a team queue. \
+                Can be given more than once; giving it replaces the default of nobody@mozilla.org.")
+            .takes_value(true)
+            .value_name("EMAIL")
+            .multiple_occurrences(true)
+            .default_value("nobody@mozilla.org"),
+    )
+    .arg(
+        Arg::new("assignee")
+            .long("assignee")
+            .help("Only report on the bugs assigned to this person")
+            .long_help("Only report on the bugs that would be assigned to this JIRA user, or are assigned to \
+                this Bugzilla account, e.g. to go over someone's work before a 1:1.")
+            .takes_value(true)
+            .value_name("EMAIL"),
+    )
+    .arg(
+        Arg::new("buglist")
+            .long("buglist")
+            .help("Print a Bugzilla link to all the open bugs")
+            .long_help("Print a single buglist.cgi link listing every bug whose JIRA issue is still open, for bulk triage."),
+    )
+    .arg(
+        Arg::new("fail-on-changes")
+            .long("fail-on-changes")
+            .help("Exit with an error if anything needs changing")
+            .long_help("Exit with status 1 when the report finds changes, so scheduled jobs can alert on them."),
+    )
+    .arg(
+        Arg::new("epic-field")
+            .long("epic-field")
+            .help("The id of JIRA's Epic Link field")
+            .takes_value(true)
+            .value_name("FIELD")
+            .default_value("customfield_10014"),
+    )
+    .arg(
+        Arg::new("points-field")
+            .long("points-field")
+            .help("The id of JIRA's Story Points field")
+            .takes_value(true)
+            .value_name("FIELD")
+            .default_value("customfield_10037"),
+    )
+    .arg(
+        Arg::new("sprint-field")
+            .long("sprint-field")
+            .help("The id of JIRA's Sprint field")
+            .takes_value(true)
+            .value_name("FIELD")
+            .default_value("customfield_10020"),
+    )
+    .arg(
+        Arg::new("slack-webhook")
+            .long("slack-webhook")
+            .help("Post a summary of the changes to this Slack incoming webhook")
+            .long_help("When there are changes, post how many there are in each section, \
+                with a few examples, to this Slack incoming webhook.")
+            .takes_value(true)
+            .value_name("URL")
+            .env("SLACK_WEBHOOK_URL")
+            .hide_env_values(true),
+    )
+    .arg(
+        Arg::new("slack-always")
+            .long("slack-always")
+            .help("Post to Slack even when there's nothing to change")
+            .requires("slack-webhook"),
+    )
+    .arg(
+        Arg::new("epic-names")
+            .long("epic-names")
+            .help("Show which epic each issue belongs to")
+            .long_help("Look up the summary of each issue's epic, and include it in the report."),
+    )
+    .arg(
+        Arg::new("check-epics")
+            .long("check-epics")
+            .help("Report open issues whose epic is Done")
+            .long_help("Look up each issue's epic, and report the issues still open under an epic that's already Done, \
+                which should probably move to another epic."),
+    )
+    .arg(
+        Arg::new("phabricator-url")
+            .long("phabricator-url")
+            .help("The Phabricator instance to use")
+            .takes_value(true)
+            .value_name("URL")
+            .env("PHABRICATOR_URL")
+            .default_value(DEFAULT_PHABRICATOR),
+    )
+    .arg(
+        Arg::new("phabricator-token")
+            .long("phabricator-token")
+            .help("A Conduit API token, to look for stale reviews")
+            .long_help("A Conduit API token. When set, look up the Phabricator revisions attached to each bug \
+                and list the ones that have been waiting for review longer than --stale-review-days.")
+            .takes_value(true)
+            .value_name("TOKEN")
+            .env("PHABRICATOR_API_TOKEN")
+            .hide_env_values(true),
+    )
+    .arg(
+        Arg::new("stale-review-days")
+            .long("stale-review-days")
+            .help("How long a revision can wait for review before we list it")
+            .takes_value(true)
+            .value_name("DAYS")
+            .default_value("7"),
+    )
+    .arg(bug_status::config::config_arg())
+    .arg(bug_status::http::dump_raw_arg())
+    .subcommand(bug_status::completions_command());
+    let matches = bug_status::config::get_matches(&app)?;
+    if let Some(dir) = matches.value_of("dump-raw") {
+        bug_status::http::dump_raw(dir)?;
+    }
+    if let Some(("completions", matches)) = matches.subcommand() {
+        bug_status::print_completions(app, matches);
+        return Ok(());
+    }
     let project = matches.value_of("project").unwrap().to_owned();
-    println!("Getting status for \"{}\"", project);
+    let format = matches.value_of("format").unwrap().to_owned();
+    let default_assignee = matches.value_of("default-assignee");
+    let product_assignees = matches.values_of("product-assignee").map_or(Ok(vec![]), |values| values.map(|value| {
+        value.split_once('=').ok_or_else(|| eyre!("--product-assignee should look like PRODUCT=EMAIL, not {}", value))
+    }).collect::<Result<Vec<_>>>())?;
+    let account_ids = matches.values_of("account-id").map_or(Ok(vec![]), |values| values.map(|value| {
+        value.split_once('=').ok_or_else(|| eyre!("--account-id should look like EMAIL=ACCOUNT_ID, not {}", value))
+    }).collect::<Result<Vec<_>>>())?;
+    let status_map = StatusMap::new(matches.values_of("status-map").map_or(Ok(vec![]), |values| values.map(|value| {
+        value.split_once('=')
+            .map(|(jira, bugzilla)| (jira.to_string(), bugzilla.to_string()))
+            .ok_or_else(|| eyre!("--status-map should look like JIRA=BUGZILLA, not {}", value))
+    }).collect::<Result<Vec<_>>>())?);
+    let servers = Servers::new(
+        matches.value_of("bugzilla-url").unwrap(),
+        matches.value_of("jira-url").unwrap(),
+    );
+    let dataset = matches.value_of("json-input").map(Dataset::load).transpose()?;
+    if dataset.is_none() {
+        info!("Getting status for \"{}\"", project);
+    }
 
-    let cache_name = "jira.cache";
-    let cache = Path::new(&cache_name);
-    if !cache.is_file() {
-        let cache_file = File::create(&cache_name)?;
-        to_writer_pretty(cache_file, &Map::new())?;
+    let custom_fields = CustomFields {
+        epic: matches.value_of("epic-field").unwrap().to_string(),
+        points: matches.value_of("points-field").unwrap().to_string(),
+        sprints: matches.value_of("sprint-field").unwrap().to_string(),
     };
-    let cache_file = File::open(&cache_name)?;
-    let parsed_data = from_reader(cache_file);
-    if parsed_data.is_err() {
-        remove_file(cache)?;
-    }
-    let mut cached_data: Map<String, Value> = parsed_data?;
-    println!("Found {} items in the cache.", cached_data.len());
-
-    let issues = get_list(&project)?;
-    let mut bugs = get_bugs(issues, &mut cached_data)?;
-
-    // `create` will also truncate an existing file.
-    let cache_file = File::create(&cache_name)?;
-    to_writer_pretty(cache_file, &cached_data)?;
-
-    // println!(
-    //     "Open:\nhttps://bugzilla.mozilla.org/buglist.cgi?bug_id={}",
-    //     bugs.iter()
-    //         .filter_map(|item| {
-    //             if item.jira.status != "Closed" {
-    //                 Some(item.id.as_str())
-    //             } else {
-    //                 None
-    //             }
-    //         })
-    //         .collect::<Vec<_>>()
-    //         .join(",")
-    // );
-
-    let mut need_changes = false;
-    let mut header = false;
-    for bug in bugs.iter_mut() {
-        if bug.get_jira_status() == "Open" && bug.assignee.is_some() {
-            if !header {
-                println!("\n\nAssigned bugs that are still NEW:");
-                header = true;
+    if dataset.is_none() {
+        check_custom_fields(&servers, &custom_fields)?;
+    }
+
+    let compress_cache = matches.is_present("compress-cache");
+    let (cache_name, existing_cache) = cache_paths("jira.cache", compress_cache);
+    // A replay shouldn't depend on, or change, what earlier runs cached.
+    let use_cache = !matches.is_present("no-cache") && dataset.is_none();
+    let mut cached_data: Map<String, Value> = match existing_cache.filter(|_| use_cache) {
+        Some(cache) => {
+            let parsed_data = read_cache(&cache);
+            if parsed_data.is_err() {
+                remove_file(&cache)?;
             }
-            println!(
-                "  https://bugzilla.mozilla.org/show_bug.cgi?id={} (NEW) => (ASSIGNED to {:?})",
-                bug.id,
-                bug.assignee.as_ref().unwrap()
-            );
+            parsed_data?
+        }
+        None => Map::new(),
+    };
+    info!("Found {} items in the cache.", cached_data.len());
+
+    let scope = if let Some(key) = matches.value_of("jira-project") {
+        get_project_jql(key)
+    } else {
+        get_jql(&project)
+    };
+    let jql = if let Some(jql) = matches.value_of("jql") {
+        jql.to_string()
+    } else {
+        format!("statusCategory != Done AND {}", scope)
+    };
+    let status_suffixes = matches.values_of("status-suffix").unwrap().collect::<Vec<_>>();
+    let mut issues = match &dataset {
+        Some(dataset) => dataset.get_list(&status_suffixes, &custom_fields),
+        None => get_list(&jql, &servers, &status_suffixes, &custom_fields)?,
+    };
+    let limit = matches.value_of("limit").map(|limit| limit.parse::<usize>()).transpose()?;
+    if let Some(limit) = limit {
+        issues.truncate(limit);
+    }
+    let only: Option<Vec<&str>> = matches.values_of("only-categories").map(|values| {
+        values.map(|value| CATEGORIES.iter().find(|(name, _)| *name == value).unwrap().1).collect()
+    });
+    let mut broken_links = vec![];
+    let mut missing_links = vec![];
+    let jobs = matches.get_one::<u64>("jobs").map(|jobs| *jobs as usize);
+    SHOW_REQUESTS.store(matches.is_present("show-requests"), Ordering::Relaxed);
+    let (bug_cache_name, existing_bug_cache) = cache_paths("bugs.cache", compress_cache);
+    let mut bug_cache = if let Some(minutes) = matches.value_of("bug-cache-ttl").filter(|_| use_cache) {
+        let bugs = existing_bug_cache
+            .and_then(|cache| read_cache(&cache).ok())
+            .unwrap_or_default();
+        Some(BugCache { bugs, ttl: Duration::minutes(minutes.parse()?) })
+    } else {
+        None
+    };
+    // How many links came from the cache, out of how many we looked up.
+    let mut link_hits = (0, 0);
+    let mut bugs = match &dataset {
+        Some(dataset) => dataset.get_bugs(issues, &mut broken_links, &mut missing_links),
+        None => {
+            let (bugs, hits) = get_bugs(issues, &mut cached_data, &mut broken_links, &mut missing_links, jobs, &servers, bug_cache.as_mut())?;
+            link_hits = hits;
+            bugs
+        }
+    };
+    // Issues that were closed in JIRA recently, to catch the ones closed before their bugs were fixed.
+    let mut done_bugs = match matches.value_of("check-done").filter(|_| dataset.is_none()) {
+        Some(days) => {
+            let jql = format!("statusCategory = Done AND statusCategoryChangedDate >= -{}d AND {}", days.parse::<u64>()?, scope);
+            let issues = get_list(&jql, &servers, &status_suffixes, &custom_fields)?;
+            // Done issues with broken links don't need fixing, so there's no need to report them.
+            let (bugs, (hits, total)) = get_bugs(issues, &mut cached_data, &mut vec![], &mut vec![], jobs, &servers, bug_cache.as_mut())?;
+            link_hits = (link_hits.0 + hits, link_hits.1 + total);
+            bugs
+        }
+        None => vec![],
+    };
+    if dataset.is_none() {
+        info!("Link cache hits: {}/{}.", link_hits.0, link_hits.1);
+    }
+    // The main query leaves out Done issues, so look for the ones still in a sprint separately.
+    // A custom --jql says what to look at, so we leave it be.
+    let wants_sprints = only.as_ref().is_none_or(|only| only.contains(&"sprint"));
+    let sprint_issues = if dataset.is_none() && matches.value_of("jql").is_none() && wants_sprints {
+        let jql = format!("statusCategory = Done AND sprint in openSprints() AND {}", scope);
+        match get_list(&jql, &servers, &status_suffixes, &custom_fields) {
+            Ok(mut issues) => {
+                if let Some(limit) = limit {
+                    issues.truncate(limit);
+                }
+                issues
+            }
+            // Not every JIRA has sprints, and the rest of the report is still worth having.
+            Err(err) => {
+                warn!("Could not look for Done issues in open sprints, so they're left out: {:#}", err);
+                vec![]
+            }
+        }
+    } else {
+        vec![]
+    };
+    if let Some(bug_cache) = &bug_cache {
+        write_cache(&bug_cache_name, &bug_cache.bugs)?;
+    }
+    let placeholders = matches.values_of("unassigned").unwrap().collect::<Vec<_>>();
+    for bug in bugs.iter_mut().chain(done_bugs.iter_mut()) {
+        bug.clear_placeholder_assignee(&placeholders);
+    }
+    // Only look at one person's bugs, by who they'd be assigned to in JIRA or their Bugzilla email.
+    let (mut bugs, done_bugs, sprint_issues) = match matches.value_of("assignee") {
+        Some(who) => {
+            // Issues without a usable bug aren't anyone's.
+            broken_links.clear();
+            missing_links.clear();
+            let assigned = |bug: &BugzillaBug| {
+                bug.get_jira_assignee(&product_assignees, default_assignee).is_some_and(|x| x.eq_ignore_ascii_case(who))
+                    || bug.assignee.as_ref().is_some_and(|x| x.eq_ignore_ascii_case(who))
+            };
+            (
+                bugs.into_iter().filter(assigned).collect::<Vec<_>>(),
+                done_bugs.into_iter().filter(assigned).collect::<Vec<_>>(),
+                sprint_issues
+                    .into_iter()
+                    .filter(|issue| issue.assignee.as_ref().and_then(|user| user.is(who, &account_ids)) == Some(true))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        None => (bugs, done_bugs, sprint_issues),
+    };
+
+    if use_cache {
+        write_cache(&cache_name, &cached_data)?;
+    }
+
+    let epics = if dataset.is_none() && (matches.is_present("epic-names") || matches.is_present("check-epics")) {
+        get_epics(&bugs, &servers)?
+    } else {
+        HashMap::new()
+    };
+    // Only name the epics in the report when asked to, even if we looked them up for --check-epics.
+    let epic_names = match &dataset {
+        _ if !matches.is_present("epic-names") => HashMap::new(),
+        Some(dataset) => dataset.epics
+            .iter()
+            .map(|(key, name)| (key.clone(), Epic { name: name.clone(), status: String::new(), done: false }))
+            .collect(),
+        None => epics.clone(),
+    };
+    if dataset.is_some() && matches.is_present("check-epics") {
+        warn!("--json-input doesn't have the epics' statuses, so --check-epics can't check them");
+    }
+
+    let revisions = if let Some(token) = matches.value_of("phabricator-token").filter(|_| dataset.is_none()) {
+        let ids = bugs.iter().flat_map(|bug| bug.revisions.iter().copied()).collect::<Vec<_>>();
+        phabricator::get_revisions(matches.value_of("phabricator-url").unwrap(), token, &ids)?
+    } else {
+        HashMap::new()
+    };
+
+    let out = bug_status::open_output(matches.value_of("output"))?;
+    let mut report = Report::new(&format, matches.is_present("fail-on-changes"), out);
+    report.only = only;
+    if matches.is_present("buglist") {
+        let open = bugs
+            .iter()
+            .filter(|bug| !bug.jira.is_closed())
+            .map(|bug| bug.id.as_str())
+            .collect::<Vec<_>>();
+        if !open.is_empty() {
+            report.section("Open:")?;
+            report.text(format!("{}/buglist.cgi?bug_id={}", servers.bugzilla, open.join(",")))?;
+        }
+    }
+
+    for bug in bugs.iter_mut() {
+        if bug.get_jira_status() != "Open" {
+            continue;
+        }
+        if let Some(assignee) = bug.assignee_label() {
+            report.add(
+                "Assigned bugs that are still NEW:",
+                Change::new("assigned_still_new", "status", bug, Value::from(bug.status.clone()), Value::from("ASSIGNED")),
+                format!("  {} (NEW) => (ASSIGNED to {:?})",
+                    servers.bug_url(&bug.id), assignee),
+            )?;
             bug.status = "ASSIGNED".to_string();
         }
     }
-    need_changes |= header;
 
-    let mut header = false;
     for bug in &bugs {
         if bug.assignee.is_some() && bug.points.is_none() {
-            if !header {
-                println!("\n\nMissing points:");
-                header = true;
-            }
-            println!("  https://bugzilla.mozilla.org/show_bug.cgi?id={} ({:?}) => ({:?})",
-                bug.id, bug.points, bug.jira.points);
+            report.add(
+                "Missing points:",
+                Change::new("missing_points", "points", bug, points_value(bug.points), points_value(bug.jira.points)),
+                format!("  {} ({:?}) => ({:?})",
+                    servers.bug_url(&bug.id), bug.points, bug.jira.points),
+            )?;
         }
     }
-    need_changes |= header;
 
-    let mut header = false;
+    // Points that disagree need someone to decide, so they come before the ones JIRA is just missing.
     for bug in &bugs {
-        if bug.points.is_some() && bug.points != bug.jira.points {
-            if !header {
-                println!("\n\nChanged points:");
-                header = true;
+        if let (Some(jira_points), Some(points)) = (bug.jira.points, bug.points) {
+            if (jira_points - points).abs() > f64::EPSILON {
+                report.add(
+                    "Conflicting points:",
+                    Change::new("changed_points", "points", bug, points_value(Some(jira_points)), points_value(Some(points))),
+                    format!("  {}{} ({} in JIRA) => ({} in Bugzilla)",
+                        servers.bug_url(&bug.id), bug.summary_label(), jira_points, points),
+                )?;
             }
-            println!("  https://bugzilla.mozilla.org/show_bug.cgi?id={} ({:?}) => ({:?})",
-                bug.id, bug.jira.points, bug.points);
         }
     }
-    need_changes |= header;
 
-    let mut header = false;
     for bug in &bugs {
-        if bug.get_jira_status() != bug.jira.status {
-            if !header {
-                println!("\n\nChanged status:");
-                header = true;
-            }
-            println!("  https://bugzilla.mozilla.org/show_bug.cgi?id={} ({:?}) => ({:?})",
-                bug.id, bug.jira.status, bug.get_jira_status());
+        if let (None, Some(points)) = (bug.jira.points, bug.points) {
+            report.add(
+                "Points to fill in:",
+                Change::new("missing_jira_points", "points", bug, Value::Null, points_value(Some(points))),
+                format!("  {}{} => ({})",
+                    servers.bug_url(&bug.id), bug.summary_label(), points),
+            )?;
         }
     }
-    need_changes |= header;
 
-    let mut header = false;
     for bug in &bugs {
-        if bug.assignee.is_some() && bug.get_jira_assignee() != bug.jira.assignee {
-            if !header {
-                println!("\n\nChanged assignees:");
-                header = true;
-            }
-            println!("  https://bugzilla.mozilla.org/show_bug.cgi?id={} ({:?}) => ({:?})",
-                bug.id, bug.jira.assignee, bug.assignee);
+        if bug.is_closed() && !bug.jira.is_closed() {
+            report.add(
+                "Closed in Bugzilla but open in JIRA:",
+                Change::new("closed_in_bugzilla", "status", bug, Value::from(bug.jira.status.clone()), Value::from(bug.get_jira_status())),
+                format!("  {} ({:?}) => {} ({:?}){}",
+                    servers.bug_url(&bug.id), bug.status, servers.issue_url(&bug.jira.key), bug.jira.status, bug.jira.epic_label(&epic_names)),
+            )?;
+        }
+    }
+
+    for bug in bugs.iter().chain(&done_bugs) {
+        if !bug.is_closed() && bug.jira.is_closed() {
+            report.add(
+                "Closed in JIRA but open in Bugzilla:",
+                Change::new("closed_in_jira", "status", bug, Value::from(bug.jira.status.clone()), Value::from(bug.get_jira_status())),
+                format!("  {} ({:?}) => {} ({:?}){}",
+                    servers.issue_url(&bug.jira.key), bug.jira.status, servers.bug_url(&bug.id), bug.status, bug.jira.epic_label(&epic_names)),
+            )?;
+        }
+    }
+
+    // Bugs closed on one side but not the other are already in the sections above, so don't count them twice.
+    for bug in bugs.iter().filter(|bug| bug.is_closed() == bug.jira.is_closed()) {
+        if !bug.has_jira_status(&status_map) {
+            report.add(
+                "Changed status:",
+                Change::new("changed_status", "status", bug, Value::from(bug.jira.status.clone()), Value::from(bug.get_jira_status())),
+                format!("  {}{} ({:?}) => ({:?}){}",
+                    servers.bug_url(&bug.id), bug.summary_label(), bug.jira.status, bug.get_jira_status(), bug.jira.epic_label(&epic_names)),
+            )?;
+        }
+    }
+
+    for bug in &bugs {
+        if bug.assignee.is_some() && bug.jira.has_assignee(&bug.get_jira_assignee(&product_assignees, default_assignee), &account_ids) == Some(false) {
+            report.add(
+                "Changed assignees:",
+                Change::new("changed_assignee", "assignee", bug, Value::from(bug.jira.assignee_label()), Value::from(bug.get_jira_assignee(&product_assignees, default_assignee))),
+                format!("  {}{} ({:?}) => ({:?})",
+                    servers.bug_url(&bug.id), bug.summary_label(), bug.jira.assignee_label(), bug.assignee_label()),
+            )?;
+        }
+    }
+
+    for bug in &bugs {
+        if bug.assignee.is_some() && bug.jira.has_assignee(&bug.get_jira_assignee(&product_assignees, default_assignee), &account_ids).is_none() {
+            report.add(
+                "Unknown assignees:",
+                Change::new("unknown_assignee", "assignee", bug, Value::from(bug.jira.assignee_label()), Value::from(bug.get_jira_assignee(&product_assignees, default_assignee))),
+                format!("  {}{} ({:?}, whose email JIRA hides; give their --account-id to check) => ({:?})",
+                    servers.bug_url(&bug.id), bug.summary_label(), bug.jira.assignee_label(), bug.assignee_label()),
+            )?;
         }
     }
-    need_changes |= header;
 
-    let mut header = false;
     for bug in &bugs {
         if bug.jira.epic.is_none() {
-            if !header {
-                println!("\n\nMissing epics:");
-                header = true;
+            report.add(
+                "Missing epics:",
+                Change::new("missing_epic", "epic", bug, Value::Null, Value::Null),
+                format!("  {}{} => {}",
+                    servers.bug_url(&bug.id), bug.summary_label(), servers.issue_url(&bug.jira.key)),
+            )?;
+        }
+    }
+
+    // Work that's still going on under a finished epic has lost track of its plan.
+    if matches.is_present("check-epics") {
+        for bug in bugs.iter().filter(|bug| !bug.jira.is_closed()) {
+            if let Some((key, epic)) = bug.jira.epic.as_ref().and_then(|key| Some((key, epics.get(key)?))) {
+                if epic.done {
+                    report.add(
+                        "Open issues in an epic that's Done:",
+                        Change::new("epic_done", "epic", bug, Value::from(key.clone()), Value::Null),
+                        format!("  {} ({:?}) => epic {} ({}) is {:?}",
+                            servers.issue_url(&bug.jira.key), bug.jira.status, servers.issue_url(key), epic.name, epic.status),
+                    )?;
+                }
             }
-            println!("  https://bugzilla.mozilla.org/show_bug.cgi?id={} => https://mozilla-hub.atlassian.net/browse/{}",
-                bug.id, bug.jira.key);
         }
     }
-    need_changes |= header;
 
-    let mut header = false;
     for bug in &bugs {
         // if the status is "in progress" or better and there's no sprint, do something.
-        if !["Open".to_string(), "Reopened".to_string()].contains(&bug.jira.status) && bug.jira.sprints.is_empty() {
-            if !header {
-                println!("\n\nMissing sprints:");
-                header = true;
+        // Closed sprints don't count, since we can't plan work into them.
+        if !["Open", "Reopened"].iter().any(|status| bug.jira.has_status(status)) && !bug.jira.sprints.iter().any(|sprint| sprint.is_open()) {
+            let last_sprint = bug.jira.sprints.last().map(|sprint| sprint.name.clone());
+            let line = if let Some(sprint) = &last_sprint {
+                format!("  {} ({:?}, last in closed sprint {:?}){}",
+                    servers.issue_url(&bug.jira.key), bug.jira.status, sprint, bug.jira.epic_label(&epic_names))
+            } else {
+                format!("  {} ({:?}){}",
+                    servers.issue_url(&bug.jira.key), bug.jira.status, bug.jira.epic_label(&epic_names))
+            };
+            report.add(
+                "Missing sprints:",
+                Change::new("missing_sprint", "sprint", bug, Value::from(last_sprint), Value::Null),
+                line,
+            )?;
+        }
+    }
+
+    // Finished work left in the current sprint clutters its board.
+    for bug in bugs.iter().chain(&done_bugs) {
+        if !bug.jira.is_closed() {
+            continue;
+        }
+        for sprint in bug.jira.sprints.iter().filter(|sprint| sprint.is_active()) {
+            report.add(
+                "Closed issues still in an active sprint:",
+                Change::new("closed_in_sprint", "sprint", bug, Value::from(sprint.name.clone()), Value::Null),
+                format!("  {} ({:?}) => remove from {:?}{}",
+                    servers.issue_url(&bug.jira.key), bug.jira.status, sprint.name, bug.jira.epic_label(&epic_names)),
+            )?;
+        }
+    }
+    let checked = bugs.iter().chain(&done_bugs).map(|bug| &bug.jira.key).collect::<Vec<_>>();
+    for issue in sprint_issues.iter().filter(|issue| issue.is_closed() && !checked.contains(&&issue.key)) {
+        for sprint in issue.sprints.iter().filter(|sprint| sprint.is_active()) {
+            report.add(
+                "Closed issues still in an active sprint:",
+                Change {
+                    kind: "closed_in_sprint",
+                    bug: None,
+                    jira: issue.key.clone(),
+                    field: "sprint",
+                    old: Value::from(sprint.name.clone()),
+                    new: Value::Null,
+                    detail: None,
+                },
+                format!("  {} ({:?}) => remove from {:?}{}",
+                    servers.issue_url(&issue.key), issue.status, sprint.name, issue.epic_label(&epic_names)),
+            )?;
+        }
+    }
+
+    let stale_days = matches.value_of("stale-review-days").unwrap().parse::<i64>()?;
+    let stale_section = format!("Reviews waiting for more than {} days:", stale_days);
+    for bug in &bugs {
+        for revision in bug.revisions.iter().filter_map(|id| revisions.get(id)) {
+            if revision.needs_review() && revision.age().num_days() >= stale_days {
+                report.add(
+                    &stale_section,
+                    Change {
+                        detail: Some(format!("D{}", revision.id)),
+                        ..Change::new("stale_review", "review", bug, Value::from(revision.status.clone()), Value::Null)
+                    },
+                    format!("  {} => {} ({}, {} days)",
+                        servers.bug_url(&bug.id), revision.url(matches.value_of("phabricator-url").unwrap()), revision.status_name, revision.age().num_days()),
+                )?;
             }
-            println!("  https://mozilla-hub.atlassian.net/browse/{} ({:?})",
-                bug.jira.key, bug.jira.status);
         }
     }
-    need_changes |= header;
 
     let mut priority_map = HashMap::from([
         ("P1", vec![]),
@@ -502,29 +1619,88 @@ fn main() -> Result<()> {
     let mut header = false;
     for bug in bugs.iter_mut() {
         if bug.jira.priority != bug.get_jira_priority() {
-            if !header {
-                println!("\n\nJIRA tickets with wrong priority:");
-                header = true;
-            }
+            header = true;
             if priority_map.contains_key(bug.priority.as_str()) {
                 let bugs_with_prio: &mut Vec<&mut BugzillaBug> = priority_map.get_mut(bug.priority.as_str()).unwrap();
                 bugs_with_prio.push(bug);
             }
         }
     }
-    if header {
+    if header && report.wants("priority") {
+        report.section("JIRA tickets with wrong priority:")?;
         for (prio, bugs) in priority_map.iter() {
-            println!(" {:?}", prio);
+            report.text(format!(" {:?}", prio))?;
             for bug in bugs.iter() {
-                println!("  https://mozilla-hub.atlassian.net/browse/{} ({:?})",
-                    bug.jira.key, bug.jira.priority);
+                report.add(
+                    "JIRA tickets with wrong priority:",
+                    Change::new("wrong_priority", "priority", bug, Value::from(bug.jira.priority.clone()), Value::from(bug.get_jira_priority())),
+                    format!("  {} ({:?}){}",
+                        servers.issue_url(&bug.jira.key), bug.jira.priority, bug.jira.epic_label(&epic_names)),
+                )?;
             }
         }
     }
-    need_changes |= header;
 
-    if !need_changes {
-        println!("\n\nNo changes necessary! 🎉\n");
+    for bug in &bugs {
+        if let Some(dupe_of) = bug.dupe_of {
+            report.add(
+                "Issues linked to a duplicate bug:",
+                Change::new("duplicate_link", "link", bug, Value::from(bug.id.clone()), Value::from(dupe_of.to_string())),
+                format!("  {} => {} is a duplicate of {}",
+                    servers.issue_url(&bug.jira.key), servers.bug_url(&bug.id), servers.bug_url(&dupe_of.to_string())),
+            )?;
+        }
+    }
+
+    for key in missing_links {
+        let line = format!("  {}", servers.issue_url(&key));
+        report.note(
+            "Issues without a Bugzilla link:",
+            Change {
+                kind: "missing_link",
+                bug: None,
+                jira: key,
+                field: "link",
+                old: Value::Null,
+                new: Value::Null,
+                detail: None,
+            },
+            line,
+        )?;
+    }
+
+    for broken in broken_links {
+        let line = format!("  {} ({})",
+            servers.issue_url(&broken.key), broken.reason);
+        report.note(
+            "JIRA issues without a valid Bugzilla bug:",
+            Change {
+                kind: "broken_link",
+                bug: None,
+                jira: broken.key,
+                field: "link",
+                old: Value::Null,
+                new: Value::Null,
+                detail: Some(broken.reason),
+            },
+            line,
+        )?;
+    }
+
+    report.finish()?;
+    if let Some(webhook) = matches.value_of("slack-webhook") {
+        if report.need_changes() || matches.is_present("slack-always") {
+            let resp = reqwest::blocking::Client::new()
+                .post(webhook)
+                .json(&json!({ "text": report.slack_summary() }))
+                .send()?;
+            if !resp.status().is_success() {
+                warn!("Got {} posting to Slack", resp.status());
+            }
+        }
+    }
+    if report.need_changes() && matches.is_present("fail-on-changes") {
+        std::process::exit(1);
     }
     Ok(())
 }
@@ -535,67 +1711,227 @@ fn get_link<T: for<'de> Deserialize<'de>>(link: &str, auth: bool) -> Result<T> {
         request = request.basic_auth(JIRA_USERNAME.to_string(), Some(JIRA_PASSWORD.to_string()));
     }
     request = request.header(reqwest::header::CONTENT_TYPE, "application/json");
-    let resp = request
-        .send()
-        .unwrap_or_else(|_| panic!("Could not get data for {}", link));
-    if !resp.status().is_success() {
-        return Err(eyre!("Got {} for {}", resp.status(), link));
+    http::fetch_json(request)
+}
+
+/** Whether to show the requests in flight under the progress bars, for --show-requests. */
+static SHOW_REQUESTS: AtomicBool = AtomicBool::new(false);
+
+/** A progress bar, with a spinner under it showing the requests in flight if --show-requests was given. */
+struct Progress {
+    bar: ProgressBar,
+    /** The spinner, and the thread drawing them both */
+    requests: Option<(ProgressBar, JoinHandle<std::io::Result<()>>)>,
+}
+
+impl Progress {
+    pub fn new(len: usize, what: &str) -> Self {
+        let bar = ProgressBar::new(len as u64);
+        bar.set_style(ProgressStyle::default_bar().template(&format!(
+            "{}: {{spinner:.green}} [{{elapsed_precise}}] [{{bar:50.cyan/blue}}] ({{pos}}/{{len}}, ETA {{eta}})",
+            what
+        )));
+        if !SHOW_REQUESTS.load(Ordering::Relaxed) {
+            return Self { bar, requests: None };
+        }
+        let multi = MultiProgress::new();
+        let bar = multi.add(bar);
+        let spinner = multi.add(ProgressBar::new_spinner());
+        spinner.set_style(ProgressStyle::default_spinner().template("Requests: {spinner:.green} {msg}"));
+        spinner.enable_steady_tick(100);
+        http::watch_requests(Some(spinner.clone()));
+        // MultiProgress only draws while something is waiting on it.
+        let drawing = thread::spawn(move || multi.join());
+        Self { bar, requests: Some((spinner, drawing)) }
+    }
+
+    pub fn finish(self) {
+        self.bar.finish();
+        if let Some((spinner, drawing)) = self.requests {
+            http::watch_requests(None);
+            spinner.finish_and_clear();
+            let _ = drawing.join();
+        }
     }
-    let fields = resp
-        .json::<T>()
-        .unwrap_or_else(|_| panic!("Could not parse json from {}", link));
-    Ok(fields)
 }
 
+impl Deref for Progress {
+    type Target = ProgressBar;
+
+    fn deref(&self) -> &ProgressBar {
+        &self.bar
+    }
+}
+
+/** The --only-categories names, and the Change field each one reports on. */
+const CATEGORIES: [(&str, &str); 8] = [
+    ("statuses", "status"),
+    ("points", "points"),
+    ("assignees", "assignee"),
+    ("epics", "epic"),
+    ("sprints", "sprint"),
+    ("reviews", "review"),
+    ("priorities", "priority"),
+    ("links", "link"),
+];
+
+const BUG_FIELDS: &str = "id,summary,status,product,priority,attachments.content_type,attachments.is_obsolete,attachments.file_name,cf_fx_points,assigned_to,assigned_to_detail,last_change_time,resolution,dupe_of";
+
+/**
+ * Fetch some fields of the given bugs, 200 at a time and several requests at once,
+ * keyed by bug id and with `keep` picking out what we need from each.
+ */
+fn fetch_bugs<T: Send>(
+    ids: &[&str],
+    fields: &str,
+    servers: &Servers,
+    bar: &ProgressBar,
+    keep: impl Fn(&Map<String, Value>) -> T + Sync,
+) -> Result<HashMap<String, T>> {
+    let chunks = ids.par_chunks(200)
+        .map(|ids| {
+            let list = format!("{}/rest/bug?id={}&include_fields={}",
+                servers.bugzilla, ids.join(","), fields);
+            let bugs: HashMap<String, Value> = get_link(&list, true)
+                .wrap_err_with(|| format!("Could not get bugs {}", ids.join(", ")))?;
+            bar.inc(ids.len() as u64);
+            let bugs = bugs
+                .get("bugs")
+                .unwrap_or_else(|| panic!("Could not get bugs from {}", list))
+                .as_array()
+                .unwrap_or_else(|| panic!("Could not get bugs from {}", list));
+            let mut rv = HashMap::new();
+            for bug in bugs {
+                let bug = bug
+                    .as_object()
+                    .unwrap_or_else(|| panic!("Could not get bug from {}", &bug));
+                let id = bug
+                    .get("id")
+                    .unwrap_or_else(|| panic!("Could not get id from {:?}", bug))
+                    .as_u64()
+                    .unwrap_or_else(|| panic!("Could not get id from {:?}", bug));
+                rv.insert(id.to_string(), keep(bug));
+            }
+            Ok(rv)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(chunks.into_iter().flatten().collect())
+}
+
+/** The bugs the issues link to, and how many of the links came from the cache out of how many there were. */
 fn get_bugs(
     issues: Vec<JiraIssue>,
     cached_data: &mut Map<String, Value>,
-) -> Result<Vec<BugzillaBug>> {
-    let bar = ProgressBar::new(issues.len() as u64);
-    bar.set_style(ProgressStyle::default_bar().template(
-        "Getting links: {spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] ({pos}/{len}, ETA {eta})",
-    ));
-
-    let links: Vec<BugzillaJiraLink> = issues
-        .into_par_iter()
-        .map(|issue| {
-            bar.inc(1);
-            BugzillaJiraLink::new(issue, &cached_data)
-        })
-        .filter_map(|x| x)
-        .collect();
+    broken_links: &mut Vec<BrokenLink>,
+    missing_links: &mut Vec<String>,
+    jobs: Option<usize>,
+    servers: &Servers,
+    mut bug_cache: Option<&mut BugCache>,
+) -> Result<(Vec<BugzillaBug>, (usize, usize))> {
+    let bar = Progress::new(issues.len(), "Getting links");
+
+    let get_links = || {
+        issues
+            .into_par_iter()
+            .map(|issue| {
+                bar.inc(1);
+                let key = issue.key.clone();
+                BugzillaJiraLink::new(issue, cached_data, servers).map(|link| link.ok_or(key))
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    // Use a smaller pool if we've been asked to go easy on JIRA.
+    let links = if let Some(jobs) = jobs {
+        ThreadPoolBuilder::new().num_threads(jobs).build()?.install(get_links)?
+    } else {
+        get_links()?
+    };
     bar.finish();
+    let links = links
+        .into_iter()
+        .filter_map(|link| match link {
+            Ok(link) => {
+                // Wait until the bar's done to say so, or the warnings break it up.
+                if !link.others.is_empty() {
+                    warn!("Multiple Bugzilla links for {} ({}, {}), using {}",
+                        servers.issue_url(&link.jira.key), link.bugzilla, link.others.join(", "), link.bugzilla);
+                }
+                Some(link)
+            }
+            Err(key) => {
+                missing_links.push(key);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if !missing_links.is_empty() {
+        warn!("{} issues have no Bugzilla link, see the end of the report.", missing_links.len());
+    }
+    let link_hits = (links.iter().filter(|link| link.cached).count(), links.len());
 
     let mut bz_statuses = HashMap::new();
-    let bar = ProgressBar::new(links.len() as u64);
-    bar.set_style(ProgressStyle::default_bar().template(
-        "Getting bugs: {spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] ({pos}/{len}, ETA {eta})",
-    ));
-    for links in links.chunks(200) {
-        let list = format!("https://bugzilla.mozilla.org/rest/bug?id={}&include_fields=id,summary,status,product,priority,attachments.content_type,attachments.is_obsolete,cf_fx_points,assigned_to",
-            links.iter().map(|x| x.bugzilla.as_str()).collect::<Vec<_>>().join(","));
-        let bugs: HashMap<String, Value> = get_link(&list, true).unwrap();
-        bar.inc(links.len() as u64);
-        let bugs = bugs
-            .get("bugs")
-            .unwrap_or_else(|| panic!("Could not get bugs from {}", list))
-            .as_array()
-            .unwrap_or_else(|| panic!("Could not get bugs from {}", list));
-        for bug in bugs {
-            let bug = bug
-                .as_object()
-                .unwrap_or_else(|| panic!("Could not get bug from {}", &bug));
-            let id = bug
-                .get("id")
-                .unwrap_or_else(|| panic!("Could not get id from {:?}", bug))
-                .as_u64()
-                .unwrap_or_else(|| panic!("Could not get id from {:?}", bug));
-            bz_statuses.insert(id.to_string(), bug.clone());
+    let mut ids = links
+        .iter()
+        .map(|x| x.bugzilla.as_str())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.dedup();
+    let now = Utc::now();
+    let mut to_fetch = ids.clone();
+    if let Some(cache) = bug_cache.as_deref_mut() {
+        // Trust recent entries outright, and only re-fetch older ones if they've changed since.
+        let mut stale = vec![];
+        to_fetch.clear();
+        for id in ids {
+            match cache.bugs.get(id) {
+                Some(cached) if now - cached.fetched < cache.ttl => {
+                    bz_statuses.insert(id.to_string(), cached.bug.clone());
+                }
+                Some(_) => stale.push(id),
+                None => to_fetch.push(id),
+            }
         }
+        let bar = Progress::new(stale.len(), "Checking bugs");
+        let changes = fetch_bugs(&stale, "id,last_change_time", servers, &bar, |bug| {
+            bug.get("last_change_time").and_then(|x| x.as_str()).map(|x| x.to_string())
+        })?;
+        bar.finish();
+        for id in stale {
+            let cached = cache.bugs.get_mut(id).unwrap();
+            let changed = changes.get(id).cloned().flatten();
+            let cached_changed = cached.bug.as_ref().and_then(|bug| bug.last_change_time.clone());
+            if changed.is_some() && changed == cached_changed {
+                cached.fetched = now;
+                bz_statuses.insert(id.to_string(), cached.bug.clone());
+            } else {
+                to_fetch.push(id);
+            }
+        }
+        info!("Using {} cached bugs, fetching {}.", bz_statuses.len(), to_fetch.len());
     }
+
+    let bar = Progress::new(to_fetch.len(), "Getting bugs");
+    let fetched = fetch_bugs(&to_fetch, BUG_FIELDS, servers, &bar, BugFields::from_json)?;
     bar.finish();
+    if let Some(cache) = bug_cache {
+        for (id, bug) in &fetched {
+            cache.bugs.insert(id.clone(), CachedBug { fetched: now, bug: bug.clone() });
+        }
+    }
+    bz_statuses.extend(fetched);
 
-    let bugs = links
+    Ok((link_bugs(links, &bz_statuses, cached_data, broken_links), link_hits))
+}
+
+/** Pair each link with its bug, noting the links we couldn't follow, and remember new links in the cache. */
+fn link_bugs(
+    links: Vec<BugzillaJiraLink>,
+    bz_statuses: &HashMap<String, Option<BugFields>>,
+    cached_data: &mut Map<String, Value>,
+    broken_links: &mut Vec<BrokenLink>,
+) -> Vec<BugzillaBug> {
+    links
         .into_iter()
         .filter_map(|link| {
             if !link.cached {
@@ -605,27 +1941,133 @@ fn get_bugs(
             }
 
             if link.bugzilla.is_empty() {
-                None
-            } else if !bz_statuses.contains_key(&link.bugzilla) {
-                println!("Ignoring confidential bug {}", link.bugzilla);
+                broken_links.push(BrokenLink {
+                    key: link.jira.key,
+                    reason: "empty Bugzilla link".to_string(),
+                });
                 None
             } else {
-                Some(BugzillaBug::new(link, &bz_statuses))
+                match bz_statuses.get(&link.bugzilla) {
+                    None => {
+                        broken_links.push(BrokenLink {
+                            key: link.jira.key,
+                            reason: format!("bug {} is private or deleted", link.bugzilla),
+                        });
+                        None
+                    }
+                    Some(None) => {
+                        warn!("Skipping restricted bug {}", link.bugzilla);
+                        broken_links.push(BrokenLink {
+                            key: link.jira.key,
+                            reason: format!("bug {} is restricted", link.bugzilla),
+                        });
+                        None
+                    }
+                    Some(Some(bug)) => Some(BugzillaBug::new(link, bug)),
+                }
             }
         })
+        .collect::<Vec<_>>()
+}
+
+/**
+ * A captured set of JIRA issues, their Bugzilla links, and the bugs, for --json-input.
+ * Replaying one checks the issues without talking to JIRA or Bugzilla, so runs are reproducible.
+ */
+#[derive(Debug, Deserialize)]
+struct Dataset {
+    /** The issues, as JIRA's search API returns them */
+    issues: Vec<Value>,
+    /** Each issue's linked bug id, by JIRA key. Issues that aren't here have no link. */
+    #[serde(default)]
+    links: HashMap<String, String>,
+    /** The bugs, as Bugzilla's REST API returns them */
+    #[serde(default)]
+    bugs: Vec<Map<String, Value>>,
+    /** Epic names by JIRA key, for --epic-names */
+    #[serde(default)]
+    epics: HashMap<String, String>,
+}
+
+impl Dataset {
+    pub fn load(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(|err| eyre!("Could not open {}: {}", path, err))?;
+        from_reader(file).map_err(|err| eyre!("Could not parse {}: {}", path, err))
+    }
+
+    pub fn get_list(&self, status_suffixes: &[&str], custom_fields: &CustomFields) -> Vec<JiraIssue> {
+        self.issues
+            .iter()
+            .map(|issue| JiraIssue::new(issue, status_suffixes, custom_fields))
+            .collect()
+    }
+
+    /** The same as get_bugs, but with the links and bugs from the dataset. */
+    pub fn get_bugs(
+        &self,
+        issues: Vec<JiraIssue>,
+        broken_links: &mut Vec<BrokenLink>,
+        missing_links: &mut Vec<String>,
+    ) -> Vec<BugzillaBug> {
+        let mut links = vec![];
+        for jira in issues {
+            match self.links.get(&jira.key) {
+                Some(bugzilla) => links.push(BugzillaJiraLink { bugzilla: bugzilla.clone(), jira, cached: true, others: vec![] }),
+                None => missing_links.push(jira.key),
+            }
+        }
+        let bz_statuses = self.bugs
+            .iter()
+            .filter_map(|bug| Some((bug.get("id")?.as_u64()?.to_string(), BugFields::from_json(bug))))
+            .collect::<HashMap<_, _>>();
+        link_bugs(links, &bz_statuses, &mut Map::new(), broken_links)
+    }
+}
+
+/** Which issues belong to a --project preset, whatever their status. */
+fn get_jql(project: &str) -> String {
+    match project {
+        "mv3" => get_project_jql("WEBEXT"),
+        "mr22" => "( \"Epic Link\"= FIDEFE-2368 OR \"Epic Link\" = FIDEFE-2157 )".to_string(),
+        _ => get_project_jql("FIDEFE"),
+    }
+}
+
+fn get_project_jql(key: &str) -> String {
+    format!("project = {} AND type != Epic", key)
+}
+
+/** Fail before doing any real work if JIRA doesn't have the custom fields we were told to read. */
+fn check_custom_fields(servers: &Servers, custom_fields: &CustomFields) -> Result<()> {
+    let list = format!("{}/rest/api/3/field", servers.jira);
+    // This is the first thing we ask JIRA, so stop here rather than sending bad credentials with every request.
+    let fields: Vec<Value> = get_link(&list, true).map_err(|err| match err.downcast_ref::<http::StatusError>() {
+        Some(status) if status.is_auth() => {
+            eyre!("JIRA authentication failed ({}) — check JIRA_USERNAME and JIRA_PASSWORD", status.status)
+        }
+        _ => err,
+    })?;
+    let missing = custom_fields
+        .all()
+        .iter()
+        .filter(|(_, id)| !fields.iter().any(|field| field.get("id").and_then(|x| x.as_str()) == Some(id)))
+        .map(|(flag, id)| format!("{} ({})", id, flag))
         .collect::<Vec<_>>();
-    // println!("bugs: {:#?}", &bugs[..10]);
-    Ok(bugs)
+    if !missing.is_empty() {
+        return Err(eyre!("{} doesn't have the custom fields {}. Pass the right ids with the flags in brackets.",
+            servers.jira, missing.join(", ")));
+    }
+    Ok(())
 }
 
-fn get_list(project: &str) -> Result<Vec<JiraIssue>> {
+fn get_list(jql: &str, servers: &Servers, status_suffixes: &[&str], custom_fields: &CustomFields) -> Result<Vec<JiraIssue>> {
     // Get the list of issues first.
-    let list = match project {
-        "mv3" => "https://mozilla-hub.atlassian.net/rest/api/3/search?fields=key&maxResults=1000&jql=statusCategory%20!%3D%20Done%20AND%20project%20%3D%20WEBEXT%20AND%20type%20!%3D%20Epic",
-        "mr22" => "https://mozilla-hub.atlassian.net/rest/api/3/search?fields=key&maxResults=1000&jql=statusCategory%20!%3D%20Done%20AND%20(%20%22Epic%20Link%22%3D%20FIDEFE-2368%20OR%20%22Epic%20Link%22%20%3D%20FIDEFE-2157%20)",
-        _ => "https://mozilla-hub.atlassian.net/rest/api/3/search?fields=key&maxResults=1000&jql=statusCategory%20!%3D%20Done%20AND%20project%20%3D%20FIDEFE%20AND%20type%20!%3D%20Epic",
-    };
-    let issues: HashMap<String, Value> = get_link(&list, true).unwrap();
+    let list = reqwest::Url::parse_with_params(
+        &format!("{}/rest/api/3/search", servers.jira),
+        &[("fields", "key"), ("maxResults", "1000"), ("jql", jql)],
+    )?;
+    let list = list.as_str();
+    let issues: HashMap<String, Value> = get_link(list, true).wrap_err("Could not search JIRA for the issues")?;
     let issues = issues
         .get("issues")
         .unwrap_or_else(|| panic!("Could not get issues from {}", list))
@@ -643,25 +2085,79 @@ fn get_list(project: &str) -> Result<Vec<JiraIssue>> {
 
     // Then get their statuses.
     let mut rv = vec![];
-    let bar = ProgressBar::new(issues.len() as u64);
-    bar.set_style(ProgressStyle::default_bar().template(
-        "Getting issues: {spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] ({pos}/{len}, ETA {eta})",
-    ));
-    for issues in issues.chunks(100) {
-        let list = format!("https://mozilla-hub.atlassian.net/rest/api/3/search?jql=issueKey%20in%20({})&fields=status,customfield_10014,priority,customfield_10037,customfield_10020,assignee&maxResults=1000",
-            issues.join("%2C"));
-        let issues: HashMap<String, Value> = get_link(&list, true).unwrap();
-        bar.inc(issues.len() as u64);
+    let bar = Progress::new(issues.len(), "Getting issues");
+    for keys in issues.chunks(100) {
+        let list = format!("{}/rest/api/3/search?jql=issueKey%20in%20({})&fields=status,{},priority,{},{},assignee&maxResults=1000",
+            servers.jira, keys.join("%2C"), custom_fields.epic, custom_fields.points, custom_fields.sprints);
+        let issues: HashMap<String, Value> = get_link(&list, true)
+            .wrap_err_with(|| format!("Could not get issues {}", keys.join(", ")))?;
+        bar.inc(keys.len() as u64);
         let issues = issues
             .get("issues")
             .unwrap_or_else(|| panic!("Could not get issues from {}", list))
             .as_array()
             .unwrap_or_else(|| panic!("Could not get issues from {}", list));
 
+        // JIRA can quietly return fewer issues than we asked for, which would drop them from the report.
+        if issues.len() != keys.len() {
+            let returned = issues
+                .iter()
+                .filter_map(|issue| issue.get("key").and_then(|x| x.as_str()))
+                .collect::<Vec<_>>();
+            let missing = keys.iter().filter(|key| !returned.contains(key)).copied().collect::<Vec<_>>();
+            warn!("Asked JIRA for {} issues but got {}; missing {}", keys.len(), issues.len(), missing.join(", "));
+        }
         for issue in issues {
-            rv.push(JiraIssue::new(issue));
+            rv.push(JiraIssue::new(issue, status_suffixes, custom_fields));
         }
     }
     bar.finish();
     Ok(rv)
 }
+
+/** Look up the summary and status of each issue's epic, once per epic. */
+fn get_epics(bugs: &[BugzillaBug], servers: &Servers) -> Result<HashMap<String, Epic>> {
+    let mut epics = bugs
+        .iter()
+        .filter_map(|bug| bug.jira.epic.as_deref())
+        .collect::<Vec<_>>();
+    epics.sort_unstable();
+    epics.dedup();
+
+    let mut rv = HashMap::new();
+    for epics in epics.chunks(100) {
+        let list = format!("{}/rest/api/3/search?jql=issueKey%20in%20({})&fields=summary,status&maxResults=1000",
+            servers.jira, epics.join("%2C"));
+        let epics: HashMap<String, Value> = get_link(&list, true)
+            .wrap_err_with(|| format!("Could not get the epics {}", epics.join(", ")))?;
+        let epics = epics
+            .get("issues")
+            .unwrap_or_else(|| panic!("Could not get issues from {}", list))
+            .as_array()
+            .unwrap_or_else(|| panic!("Could not get issues from {}", list));
+        for epic in epics {
+            let key = epic
+                .get("key")
+                .unwrap_or_else(|| panic!("Could not get key from {:?}", epic))
+                .as_str()
+                .unwrap_or_else(|| panic!("Could not get key from {:?}", epic));
+            let summary = epic
+                .get("fields")
+                .and_then(|fields| fields.get("summary"))
+                .and_then(|summary| summary.as_str())
+                .unwrap_or("???");
+            let status = epic.get("fields").and_then(|fields| fields.get("status"));
+            let done = status
+                .and_then(|status| status.get("statusCategory"))
+                .and_then(|category| category.get("key"))
+                .and_then(|key| key.as_str())
+                == Some("done");
+            let status = status
+                .and_then(|status| status.get("name"))
+                .and_then(|name| name.as_str())
+                .unwrap_or_default();
+            rv.insert(key.to_string(), Epic { name: summary.to_string(), status: status.to_string(), done });
+        }
+    }
+    Ok(rv)
+}
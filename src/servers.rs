@@ -0,0 +1,34 @@
+pub const DEFAULT_BUGZILLA: &str = "https://bugzilla.mozilla.org";
+pub const DEFAULT_JIRA: &str = "https://mozilla-hub.atlassian.net";
+
+/** Where to find Bugzilla and JIRA, so we can point at staging instances. */
+#[derive(Clone, Debug)]
+pub struct Servers {
+    /** The Bugzilla base URL, without a trailing slash */
+    pub bugzilla: String,
+    /** The JIRA base URL, without a trailing slash */
+    pub jira: String,
+}
+
+impl Servers {
+    pub fn new(bugzilla: &str, jira: &str) -> Self {
+        Self {
+            bugzilla: bugzilla.trim_end_matches('/').to_string(),
+            jira: jira.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn bug_url(&self, id: &str) -> String {
+        format!("{}/show_bug.cgi?id={}", self.bugzilla, id)
+    }
+
+    pub fn issue_url(&self, key: &str) -> String {
+        format!("{}/browse/{}", self.jira, key)
+    }
+}
+
+impl Default for Servers {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUGZILLA, DEFAULT_JIRA)
+    }
+}
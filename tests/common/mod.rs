@@ -0,0 +1,38 @@
+//! Helpers for running the binaries against a mock server.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/**
+ * Run one of the binaries with these arguments, from an empty directory so it won't pick up
+ * a config file or caches, without blocking the mock server's runtime. Only the variables the tests
+ * need are set, so a developer's Slack webhook or Phabricator token can't leak into the run.
+ */
+pub async fn run(binary: &'static str, name: &str, args: Vec<String>) -> Output {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    // dotenvy looks in the parent directories too, so stop it at this empty one before it finds the crate's.
+    std::fs::write(dir.join(".env"), "").unwrap();
+    tokio::task::spawn_blocking(move || {
+        Command::new(binary)
+            .args(args)
+            .current_dir(dir)
+            .env_clear()
+            .env("JIRA_USERNAME", "tester")
+            .env("JIRA_PASSWORD", "secret")
+            .env("RUST_LOG", "warn")
+            .output()
+            .unwrap()
+    })
+    .await
+    .unwrap()
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
@@ -0,0 +1,131 @@
+//! Run the summary against a fake Bugzilla.
+
+mod common;
+
+use serde_json::json;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn bugs() -> serde_json::Value {
+    let patch = json!([{"content_type": "text/x-phabricator-request", "is_obsolete": 0}]);
+    json!({"bugs": [
+        {"id": 1, "summary": "a", "status": "NEW", "resolution": "", "product": "Firefox", "priority": "P1",
+            "creation_time": "2020-01-01T00:00:00Z", "assigned_to": "nobody@mozilla.org", "attachments": []},
+        {"id": 2, "summary": "b", "status": "ASSIGNED", "resolution": "", "product": "Core", "priority": "P2",
+            "creation_time": "2020-01-01T00:00:00Z", "assigned_to": "dev@mozilla.com", "attachments": patch},
+        {"id": 3, "summary": "c", "status": "RESOLVED", "resolution": "FIXED", "product": "Core", "priority": "P3",
+            "creation_time": "2020-01-01T00:00:00Z", "assigned_to": "dev@mozilla.com", "attachments": patch},
+    ]})
+}
+
+fn args(server: &MockServer) -> Vec<String> {
+    ["--versions", "82", "--bugzilla-url", &server.uri()].iter().map(|x| x.to_string()).collect()
+}
+
+async fn mount_bugs(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/rest/bug"))
+        .and(query_param("status_whiteboard", "[print2020_v82]"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(bugs()))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn summarizes_a_version() {
+    let server = MockServer::start().await;
+    mount_bugs(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_printing"), "printing-summary", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let stdout = common::stdout(&output);
+    assert!(stdout.contains("Front-end (v82) - 0 patches landed"), "{}", stdout);
+    assert!(stdout.contains("Platform (v82) - 1 patches landed, 0 fixed without a patch, 0 closed without a fix, 1 patches up for review on 1 bugs"), "{}", stdout);
+    assert!(stdout.contains("Total Open:      1 P1s, 0 P2s"), "{}", stdout);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_server_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_bugs(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_printing"), "printing-500", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert!(common::stderr(&output).contains("500 Internal Server Error"));
+    assert!(common::stdout(&output).contains("Platform (v82) - 1 patches landed"));
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn waits_as_long_as_retry_after_says() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "2"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_bugs(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_printing"), "printing-429", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert!(common::stderr(&output).contains("429 Too Many Requests"));
+    assert!(common::stderr(&output).contains("retrying in 2s"));
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up_on_client_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_printing"), "printing-404", args(&server)).await;
+    assert!(!output.status.success());
+    assert!(common::stderr(&output).contains("404 Not Found"));
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_a_table() {
+    let server = MockServer::start().await;
+    mount_bugs(&server).await;
+
+    let mut args = args(&server);
+    args.extend(["--format", "table"].iter().map(|x| x.to_string()));
+    let output = common::run(env!("CARGO_BIN_EXE_printing"), "printing-table", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let stdout = common::stdout(&output);
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4, "{}", stdout);
+    assert!(lines[0].starts_with("version  product    landed  fixed without a patch  closed without a fix"), "{}", stdout);
+    assert!(lines[3].starts_with("v82      Platform        1                      0                     0"), "{}", stdout);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dumps_responses_to_replay() {
+    let server = MockServer::start().await;
+    mount_bugs(&server).await;
+
+    let mut args = args(&server);
+    args.extend(["--dump-raw", "raw"].iter().map(|x| x.to_string()));
+    let output = common::run(env!("CARGO_BIN_EXE_printing"), "printing-dump-raw", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let dump = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("printing-dump-raw/raw/0000-rest_bug.json");
+    let dumped: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&dump).unwrap()).unwrap();
+    assert_eq!(dumped, bugs());
+
+    let args = ["--versions", "82", "--input", dump.to_str().unwrap()].iter().map(|x| x.to_string()).collect();
+    let replayed = common::run(env!("CARGO_BIN_EXE_printing"), "printing-replay", args).await;
+    assert!(replayed.status.success(), "{}", common::stderr(&replayed));
+    assert_eq!(common::stdout(&replayed), common::stdout(&output));
+}
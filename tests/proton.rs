@@ -0,0 +1,321 @@
+//! Run the JIRA check against fake JIRA and Bugzilla servers.
+
+mod common;
+
+use serde_json::{json, Value};
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+async fn mount(server: &MockServer, url: &str, body: Value) {
+    Mock::given(method("GET"))
+        .and(path(url))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(server)
+        .await;
+}
+
+async fn mount_project(server: &MockServer) {
+    mount(server, "/rest/api/3/field", json!([
+        {"id": "customfield_10014"}, {"id": "customfield_10037"}, {"id": "customfield_10020"},
+    ])).await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(query_param("fields", "key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"issues": [{"key": "FIDEFE-1"}]})))
+        .with_priority(1)
+        .mount(server)
+        .await;
+    mount(server, "/rest/api/3/search", json!({"issues": [{
+        "key": "FIDEFE-1",
+        "self": format!("{}/rest/api/3/issue/1", server.uri()),
+        "fields": {
+            "status": {"name": "Backlog"},
+            "priority": {"id": "2"},
+            "customfield_10037": 3,
+            "assignee": null,
+        },
+    }]})).await;
+    mount(server, "/rest/api/3/issue/FIDEFE-1/remotelink", json!([
+        {"object": {"url": format!("{}/show_bug.cgi?id=100", server.uri())}},
+    ])).await;
+    mount(server, "/rest/bug", json!({"bugs": [{
+        "id": 100,
+        "summary": "Make it better",
+        "status": "ASSIGNED",
+        "priority": "P1",
+        "cf_fx_points": "5",
+        "assigned_to": "dev@mozilla.com",
+    }]})).await;
+}
+
+fn args(server: &MockServer) -> Vec<String> {
+    ["--no-cache", "--format", "json", "--jira-url", &server.uri(), "--bugzilla-url", &server.uri()]
+        .iter()
+        .map(|x| x.to_string())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_what_changed_in_bugzilla() {
+    let server = MockServer::start().await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-changes", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let find = |kind: &str| changes.iter().find(|change| change["kind"] == kind).unwrap_or_else(|| panic!("No {} in {:?}", kind, changes));
+
+    let points = find("changed_points");
+    assert_eq!((&points["bug"], &points["jira"]), (&json!("100"), &json!("FIDEFE-1")));
+    assert_eq!((&points["old"], &points["new"]), (&json!(3), &json!(5)));
+    assert_eq!(find("changed_status")["new"], "In Progress");
+    assert_eq!(find("changed_assignee")["new"], "dev@mozilla.com");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compares_half_points() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/rest/bug"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"bugs": [{
+            "id": 100,
+            "summary": "Make it better",
+            "status": "ASSIGNED",
+            "priority": "P1",
+            "cf_fx_points": "1.5",
+            "assigned_to": "dev@mozilla.com",
+        }]})))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-half-points", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let points = changes.iter().find(|change| change["kind"] == "changed_points").unwrap();
+    assert_eq!((&points["old"], &points["new"]), (&json!(3), &json!(1.5)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_jira_server_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/field"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-503", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert!(common::stderr(&output).contains("503 Service Unavailable"));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    assert!(changes.iter().any(|change| change["kind"] == "changed_points"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_when_jira_rejects_the_credentials() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-401", args(&server)).await;
+    assert!(!output.status.success());
+    assert!(common::stderr(&output).contains("JIRA authentication failed"), "{}", common::stderr(&output));
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_issues_under_a_finished_epic() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(query_param("fields", "summary,status"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"issues": [{
+            "key": "FIDEFE-9",
+            "fields": {"summary": "Last year's plan", "status": {"name": "Done", "statusCategory": {"key": "done"}}},
+        }]})))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(query_param("fields", "key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"issues": [{"key": "FIDEFE-1"}]})))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount(&server, "/rest/api/3/search", json!({"issues": [{
+        "key": "FIDEFE-1",
+        "self": format!("{}/rest/api/3/issue/1", server.uri()),
+        "fields": {"status": {"name": "In Progress"}, "priority": {"id": "1"}, "customfield_10014": "FIDEFE-9"},
+    }]})).await;
+    mount_project(&server).await;
+
+    let mut args = args(&server);
+    args.push("--check-epics".to_string());
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-epic-done", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let epic_done = changes.iter().find(|change| change["kind"] == "epic_done").unwrap_or_else(|| panic!("{:?}", changes));
+    assert_eq!((&epic_done["jira"], &epic_done["old"]), (&json!("FIDEFE-1"), &json!("FIDEFE-9")));
+
+    // Without --epic-names the text report doesn't name the epics it looked up.
+    let args = ["--no-cache", "--check-epics", "--jira-url", &server.uri(), "--bugzilla-url", &server.uri()]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-epic-done-text", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let stdout = common::stdout(&output);
+    assert!(stdout.contains("Open issues in an epic that's Done:") && !stdout.contains("belongs to epic"), "{}", stdout);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checks_hidden_emails_by_account_id() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(query_param("fields", "key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"issues": [{"key": "FIDEFE-1"}]})))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount(&server, "/rest/api/3/search", json!({"issues": [{
+        "key": "FIDEFE-1",
+        "self": format!("{}/rest/api/3/issue/1", server.uri()),
+        "fields": {
+            "status": {"name": "In Progress"},
+            "priority": {"id": "1"},
+            "assignee": {"accountId": "5b10ac8d82e05b22cc7d4ef5", "displayName": "Dev", "emailAddress": null},
+        },
+    }]})).await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-hidden-email", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let kinds = changes.iter().map(|change| change["kind"].as_str().unwrap()).collect::<Vec<_>>();
+    assert!(kinds.contains(&"unknown_assignee") && !kinds.contains(&"changed_assignee"), "{:?}", kinds);
+
+    let mut args = args(&server);
+    args.push("--account-id=dev@mozilla.com=5b10ac8d82e05b22cc7d4ef5".to_string());
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-account-id", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    assert!(changes.iter().all(|change| change["field"] != "assignee"), "{:?}", changes);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_closed_bug_once() {
+    let server = MockServer::start().await;
+    mount(&server, "/rest/bug", json!({"bugs": [{
+        "id": 100,
+        "summary": "Make it better",
+        "status": "RESOLVED",
+        "priority": "P1",
+        "cf_fx_points": "3",
+        "assigned_to": "nobody@mozilla.org",
+    }]})).await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-closed-once", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let kinds = changes.iter().filter(|change| change["field"] == "status").map(|change| &change["kind"]).collect::<Vec<_>>();
+    assert_eq!(kinds, [&json!("closed_in_bugzilla")]);
+}
+
+/** Match searches whose JQL mentions this, like "openSprints". */
+fn jql_mentions(text: &'static str) -> impl Fn(&Request) -> bool + Send + Sync {
+    move |request: &Request| request.url.query_pairs().any(|(name, value)| name == "jql" && value.contains(text))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_done_issues_left_in_a_sprint() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(jql_mentions("openSprints"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"issues": [{"key": "FIDEFE-2"}]})))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(jql_mentions("FIDEFE-2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"issues": [{
+            "key": "FIDEFE-2",
+            "self": format!("{}/rest/api/3/issue/2", server.uri()),
+            "fields": {
+                "status": {"name": "Done"},
+                "priority": {"id": "2"},
+                "customfield_10020": [{"name": "Sprint 7", "state": "active"}],
+            },
+        }]})))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-done-in-sprint", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let sprint = changes.iter().find(|change| change["kind"] == "closed_in_sprint").unwrap_or_else(|| panic!("{:?}", changes));
+    assert_eq!((&sprint["jira"], &sprint["old"]), (&json!("FIDEFE-2"), &json!("Sprint 7")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn carries_on_without_sprints() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/rest/api/3/search"))
+        .and(jql_mentions("openSprints"))
+        .respond_with(ResponseTemplate::new(400))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_project(&server).await;
+
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-no-sprints", args(&server)).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    assert!(common::stderr(&output).contains("Could not look for Done issues in open sprints"), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    assert!(changes.iter().any(|change| change["kind"] == "changed_points"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_links_dont_fail_the_run() {
+    let server = MockServer::start().await;
+    mount(&server, "/rest/api/3/issue/FIDEFE-1/remotelink", json!([])).await;
+    mount_project(&server).await;
+
+    let mut args = args(&server);
+    args.push("--fail-on-changes".to_string());
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-missing-link", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let changes: Vec<Value> = serde_json::from_str(&common::stdout(&output)).unwrap();
+    let kinds = changes.iter().map(|change| change["kind"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(kinds, ["missing_link"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sums_up_the_changes_by_section() {
+    let server = MockServer::start().await;
+    mount_project(&server).await;
+
+    let args = ["--no-cache", "--jira-url", &server.uri(), "--bugzilla-url", &server.uri()]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    let output = common::run(env!("CARGO_BIN_EXE_proton"), "proton-tally", args).await;
+    assert!(output.status.success(), "{}", common::stderr(&output));
+    let stdout = common::stdout(&output);
+    assert_eq!(stdout.lines().last(), Some("1 Conflicting points, 1 Changed status, 1 Changed assignees, 1 Missing epics, \
+        1 Missing sprints, 1 JIRA tickets with wrong priority — 6 changes needed"), "{}", stdout);
+}